@@ -0,0 +1,131 @@
+/*
+    Copyright 2022 Helsing GmbH
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+use std::fmt::UpperExp;
+use std::str::FromStr;
+
+const XSD: &str = "http://www.w3.org/2001/XMLSchema#";
+
+/// Returns the XSD canonical lexical form of a boolean or numeric literal
+///
+/// Returns `None` if the datatype is not supported or if the lexical form is not valid.
+pub fn canonical_lexical_form(datatype: &str, value: &str) -> Option<String> {
+    match datatype.strip_prefix(XSD)? {
+        "boolean" => match value {
+            "true" | "1" => Some("true".into()),
+            "false" | "0" => Some("false".into()),
+            _ => None,
+        },
+        "integer" | "nonPositiveInteger" | "negativeInteger" | "long" | "int" | "short"
+        | "byte" | "nonNegativeInteger" | "unsignedLong" | "unsignedInt" | "unsignedShort"
+        | "unsignedByte" | "positiveInteger" => canonical_integer(value),
+        "decimal" => canonical_decimal(value),
+        "double" => canonical_floating_point::<f64>(value),
+        "float" => canonical_floating_point::<f32>(value),
+        _ => None,
+    }
+}
+
+fn canonical_integer(value: &str) -> Option<String> {
+    // integer ::= [\-+]? [0-9]+
+    let (is_negative, digits) = split_sign(value);
+    if digits.is_empty() || !digits.bytes().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let digits = digits.trim_start_matches('0');
+    Some(if digits.is_empty() {
+        "0".into()
+    } else if is_negative {
+        format!("-{digits}")
+    } else {
+        digits.into()
+    })
+}
+
+fn canonical_decimal(value: &str) -> Option<String> {
+    // decimal ::= [\-+]? ([0-9]+ ('.' [0-9]*)? | '.' [0-9]+)
+    let (is_negative, number) = split_sign(value);
+    let (integer, fraction) = number.split_once('.').unwrap_or((number, ""));
+    if (integer.is_empty() && fraction.is_empty())
+        || !integer.bytes().all(|c| c.is_ascii_digit())
+        || !fraction.bytes().all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+    // We always keep a digit on both sides of the dot to stay a valid Turtle decimal
+    let integer = match integer.trim_start_matches('0') {
+        "" => "0",
+        integer => integer,
+    };
+    let fraction = match fraction.trim_end_matches('0') {
+        "" => "0",
+        fraction => fraction,
+    };
+    Some(if is_negative && (integer != "0" || fraction != "0") {
+        format!("-{integer}.{fraction}")
+    } else {
+        format!("{integer}.{fraction}")
+    })
+}
+
+fn canonical_floating_point<T: FromStr + UpperExp + PartialEq + Default>(
+    value: &str,
+) -> Option<String> {
+    // double ::= [\-+]? ([0-9]+ ('.' [0-9]*)? | '.' [0-9]+) ([Ee] [\-+]? [0-9]+)? | [\-+]? 'INF' | 'NaN'
+    match value {
+        "INF" | "+INF" => return Some("INF".into()),
+        "-INF" => return Some("-INF".into()),
+        "NaN" => return Some("NaN".into()),
+        _ => (),
+    }
+    let (is_negative, number) = split_sign(value);
+    let (mantissa, exponent) = number
+        .split_once(['e', 'E'])
+        .map_or((number, None), |(m, e)| (m, Some(e)));
+    if let Some(exponent) = exponent {
+        let (_, exponent) = split_sign(exponent);
+        if exponent.is_empty() || !exponent.bytes().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+    }
+    canonical_decimal(mantissa)?; // We check the mantissa syntax
+    let value = value.parse::<T>().ok()?;
+    if value == T::default() {
+        return Some(if is_negative {
+            "-0.0E0".into()
+        } else {
+            "0.0E0".into()
+        });
+    }
+    let formatted = format!("{value:E}");
+    if formatted.ends_with("inf") {
+        // The value overflows
+        return None;
+    }
+    Some(if formatted.contains('.') {
+        formatted
+    } else {
+        formatted.replacen('E', ".0E", 1)
+    })
+}
+
+fn split_sign(value: &str) -> (bool, &str) {
+    if let Some(value) = value.strip_prefix('-') {
+        (true, value)
+    } else {
+        (false, value.strip_prefix('+').unwrap_or(value))
+    }
+}
@@ -0,0 +1,220 @@
+/*
+    Copyright 2022 Helsing GmbH
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+use std::str::Utf8Error;
+use tree_sitter::{Node, Point};
+
+/// A position in the input file
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Position {
+    /// Line, starting from 1
+    pub line: usize,
+    /// Byte offset from the start of the line, starting from 1
+    pub column: usize,
+}
+
+/// The location of an error in the input file
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Span {
+    /// Byte range in the input file
+    pub range: Range<usize>,
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    /// Builds the span of the byte range `range` of `file`
+    pub(crate) fn new(file: &[u8], range: Range<usize>) -> Self {
+        Self {
+            start: position(file, range.start),
+            end: position(file, range.end),
+            range,
+        }
+    }
+}
+
+impl From<Node<'_>> for Span {
+    fn from(node: Node<'_>) -> Self {
+        let point = |point: Point| Position {
+            line: point.row + 1,
+            column: point.column + 1,
+        };
+        Self {
+            range: node.byte_range(),
+            start: point(node.start_position()),
+            end: point(node.end_position()),
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.start.line == self.end.line {
+            write!(
+                f,
+                "on line {} between bytes {} and {}",
+                self.start.line, self.start.column, self.end.column
+            )
+        } else {
+            write!(f, "between lines {} and {}", self.start.line, self.end.line)
+        }
+    }
+}
+
+fn position(file: &[u8], offset: usize) -> Position {
+    let before = &file[..offset];
+    let line_start = before
+        .iter()
+        .rposition(|c| *c == b'\n')
+        .map_or(0, |i| i + 1);
+    Position {
+        line: before.iter().filter(|c| **c == b'\n').count() + 1,
+        column: offset - line_start + 1,
+    }
+}
+
+/// An error making the formatting fail
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FormatError {
+    /// The file is not valid Turtle
+    Syntax {
+        span: Span,
+        /// The punctuation token that is missing, if the parser recovered by assuming one
+        missing: Option<String>,
+    },
+    /// A prefixed name is using a prefix that is not declared
+    UndefinedPrefix { span: Span, prefix: String },
+    /// An escape sequence is not valid
+    InvalidEscape { span: Span, message: String },
+    /// An IRI contains a character that is not allowed in IRIs
+    InvalidIriCharacter { span: Span, character: char },
+    /// A language tag is not valid according to BCP 47
+    InvalidLanguageTag {
+        /// The span of the invalid subtag
+        span: Span,
+        tag: String,
+        message: &'static str,
+    },
+    /// The range to format is not inside of the file
+    InvalidRange { range: Range<usize>, len: usize },
+    /// An unexpected issue, likely a bug in turtlefmt
+    Internal { message: String },
+}
+
+impl FormatError {
+    /// The location of the error in the input file, if any
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            Self::Syntax { span, .. }
+            | Self::UndefinedPrefix { span, .. }
+            | Self::InvalidEscape { span, .. }
+            | Self::InvalidIriCharacter { span, .. }
+            | Self::InvalidLanguageTag { span, .. } => Some(span),
+            Self::InvalidRange { .. } | Self::Internal { .. } => None,
+        }
+    }
+
+    /// The description of the error, without its location
+    pub fn message(&self) -> String {
+        match self {
+            Self::Syntax {
+                missing: Some(missing),
+                ..
+            } => format!(
+                "Syntax error: expected '{missing}'{}",
+                missing_hint(missing)
+            ),
+            Self::Syntax { missing: None, .. } => "Syntax error".into(),
+            Self::UndefinedPrefix { prefix, .. } => format!("The prefix {prefix}: is not defined"),
+            Self::InvalidEscape { message, .. } | Self::Internal { message } => message.clone(),
+            Self::InvalidIriCharacter { character, .. } => {
+                format!("The character {character:?} is not allowed in IRIs")
+            }
+            Self::InvalidLanguageTag { tag, message, .. } => {
+                format!("Invalid language tag '{tag}': {message}")
+            }
+            Self::InvalidRange { range, len } => {
+                format!("The range {range:?} is not inside of the {len} bytes of the file")
+            }
+        }
+    }
+}
+
+fn missing_hint(token: &str) -> &'static str {
+    match token {
+        "." => " to end the statement",
+        "]" => " to close the blank node",
+        ")" => " to close the collection",
+        ">" => " to end the IRI",
+        "\"" | "'" | "\"\"\"" | "'''" => " to end the string",
+        _ => "",
+    }
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(span) = self.span() {
+            write!(f, "{} {span}", self.message())
+        } else {
+            f.write_str(&self.message())
+        }
+    }
+}
+
+impl Error for FormatError {}
+
+/// All the errors found in a file, in the order of the file
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FormatErrors(pub Vec<FormatError>);
+
+impl fmt::Display for FormatErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, error) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{error}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for FormatErrors {}
+
+impl From<FormatError> for FormatErrors {
+    fn from(error: FormatError) -> Self {
+        Self(vec![error])
+    }
+}
+
+impl From<fmt::Error> for FormatError {
+    fn from(error: fmt::Error) -> Self {
+        Self::Internal {
+            message: error.to_string(),
+        }
+    }
+}
+
+impl From<Utf8Error> for FormatError {
+    fn from(error: Utf8Error) -> Self {
+        Self::Internal {
+            message: error.to_string(),
+        }
+    }
+}
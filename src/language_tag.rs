@@ -0,0 +1,194 @@
+/*
+    Copyright 2022 Helsing GmbH
+
+    Licensed under the Apache License, Version 2.0 (the "License");
+    you may not use this file except in compliance with the License.
+    You may obtain a copy of the License at
+
+        http://www.apache.org/licenses/LICENSE-2.0
+
+    Unless required by applicable law or agreed to in writing, software
+    distributed under the License is distributed on an "AS IS" BASIS,
+    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+    See the License for the specific language governing permissions and
+    limitations under the License.
+*/
+
+use std::iter::Peekable;
+use std::ops::Range;
+
+/// Grandfathered tags from [RFC 5646](https://www.rfc-editor.org/rfc/rfc5646#section-2.1)
+const GRANDFATHERED: &[&str] = &[
+    "en-GB-oed",
+    "i-ami",
+    "i-bnn",
+    "i-default",
+    "i-enochian",
+    "i-hak",
+    "i-klingon",
+    "i-lux",
+    "i-mingo",
+    "i-navajo",
+    "i-pwn",
+    "i-tao",
+    "i-tay",
+    "i-tsu",
+    "sgn-BE-FR",
+    "sgn-BE-NL",
+    "sgn-CH-DE",
+    "art-lojban",
+    "cel-gaulish",
+    "no-bok",
+    "no-nyn",
+    "zh-guoyu",
+    "zh-hakka",
+    "zh-min",
+    "zh-min-nan",
+    "zh-xiang",
+];
+
+/// Applies the BCP 47 case conventions: lowercase language, title case script and uppercase region
+///
+/// See [RFC 5646 section 2.1.1](https://www.rfc-editor.org/rfc/rfc5646#section-2.1.1).
+pub fn normalize_language_tag(tag: &str) -> String {
+    let mut normalized = String::with_capacity(tag.len());
+    let mut is_after_singleton = false;
+    for (i, subtag) in tag.split('-').enumerate() {
+        if i > 0 {
+            normalized.push('-');
+        }
+        if i == 0 || is_after_singleton || subtag.len() == 1 {
+            // Language, extensions and private use subtags are lowercase
+            normalized.push_str(&subtag.to_ascii_lowercase());
+            is_after_singleton |= subtag.len() == 1;
+        } else if subtag.len() == 2 {
+            // Region
+            normalized.push_str(&subtag.to_ascii_uppercase());
+        } else if subtag.len() == 4 && is_alpha(subtag) {
+            // Script
+            normalized.push_str(&subtag[..1].to_ascii_uppercase());
+            normalized.push_str(&subtag[1..].to_ascii_lowercase());
+        } else {
+            normalized.push_str(&subtag.to_ascii_lowercase());
+        }
+    }
+    normalized
+}
+
+/// An invalid subtag of a language tag
+pub struct InvalidLanguageTag {
+    /// Byte range of the invalid subtag in the tag
+    pub range: Range<usize>,
+    pub message: &'static str,
+}
+
+/// Validates that the tag follows the `Language-Tag` grammar of [RFC 5646](https://www.rfc-editor.org/rfc/rfc5646#section-2.1)
+pub fn validate_language_tag(tag: &str) -> Result<(), InvalidLanguageTag> {
+    if GRANDFATHERED.iter().any(|t| t.eq_ignore_ascii_case(tag)) {
+        return Ok(());
+    }
+
+    let mut subtags = subtags(tag).peekable();
+    let (range, language) = subtags.next().unwrap(); // split always returns an element
+    if language.eq_ignore_ascii_case("x") {
+        return validate_private_use(range, subtags);
+    }
+    if !(2..=8).contains(&language.len()) || !is_alpha(language) {
+        return Err(InvalidLanguageTag {
+            range,
+            message: "the primary language subtag must be made of 2 to 8 letters",
+        });
+    }
+    if language.len() <= 3 {
+        // Extended language subtags
+        for _ in 0..3 {
+            if subtags
+                .next_if(|(_, s)| s.len() == 3 && is_alpha(s))
+                .is_none()
+            {
+                break;
+            }
+        }
+    }
+    // Script
+    subtags.next_if(|(_, s)| s.len() == 4 && is_alpha(s));
+    // Region
+    subtags.next_if(|(_, s)| (s.len() == 2 && is_alpha(s)) || (s.len() == 3 && is_digit(s)));
+    // Variants
+    while subtags.next_if(|(_, s)| is_variant(s)).is_some() {}
+    // Extensions
+    while let Some((range, _)) =
+        subtags.next_if(|(_, s)| s.len() == 1 && is_alphanumeric(s) && !s.eq_ignore_ascii_case("x"))
+    {
+        let mut has_value = false;
+        while subtags
+            .next_if(|(_, s)| (2..=8).contains(&s.len()) && is_alphanumeric(s))
+            .is_some()
+        {
+            has_value = true;
+        }
+        if !has_value {
+            return Err(InvalidLanguageTag {
+                range,
+                message: "an extension must be followed by subtags of 2 to 8 characters",
+            });
+        }
+    }
+    match subtags.next() {
+        Some((range, s)) if s.eq_ignore_ascii_case("x") => validate_private_use(range, subtags),
+        Some((range, _)) => Err(InvalidLanguageTag {
+            range,
+            message: "this subtag is not a valid script, region, variant or extension",
+        }),
+        None => Ok(()),
+    }
+}
+
+fn validate_private_use<'a>(
+    range: Range<usize>,
+    mut subtags: Peekable<impl Iterator<Item = (Range<usize>, &'a str)>>,
+) -> Result<(), InvalidLanguageTag> {
+    if subtags.peek().is_none() {
+        return Err(InvalidLanguageTag {
+            range,
+            message: "the private use marker 'x' must be followed by subtags",
+        });
+    }
+    for (range, subtag) in subtags {
+        if !(1..=8).contains(&subtag.len()) || !is_alphanumeric(subtag) {
+            return Err(InvalidLanguageTag {
+                range,
+                message: "private use subtags must be made of 1 to 8 letters or digits",
+            });
+        }
+    }
+    Ok(())
+}
+
+fn subtags(tag: &str) -> impl Iterator<Item = (Range<usize>, &str)> {
+    let mut start = 0;
+    tag.split('-').map(move |subtag| {
+        let range = start..start + subtag.len();
+        start = range.end + 1;
+        (range, subtag)
+    })
+}
+
+fn is_variant(subtag: &str) -> bool {
+    // variant = 5*8alphanum / (DIGIT 3alphanum)
+    is_alphanumeric(subtag)
+        && ((5..=8).contains(&subtag.len())
+            || (subtag.len() == 4 && subtag.as_bytes()[0].is_ascii_digit()))
+}
+
+fn is_alpha(subtag: &str) -> bool {
+    subtag.bytes().all(|c| c.is_ascii_alphabetic())
+}
+
+fn is_digit(subtag: &str) -> bool {
+    subtag.bytes().all(|c| c.is_ascii_digit())
+}
+
+fn is_alphanumeric(subtag: &str) -> bool {
+    !subtag.is_empty() && subtag.bytes().all(|c| c.is_ascii_alphanumeric())
+}
@@ -14,20 +14,160 @@
     limitations under the License.
 */
 
-use anyhow::{anyhow, bail, Error, Result};
+use crate::canonical::canonical_lexical_form;
+use crate::language_tag::{normalize_language_tag, validate_language_tag};
+use crate::prefixes::well_known_prefix;
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::fmt::Write;
-use tree_sitter::{Language, Node};
+use std::mem::take;
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use tree_sitter::{Language, Node, Tree};
+use unicode_normalization::{is_nfc, UnicodeNormalization};
 
+mod canonical;
+mod error;
+mod language_tag;
+mod prefixes;
+
+pub use crate::error::{FormatError, FormatErrors, Position, Span};
+
+type Result<T, E = FormatError> = std::result::Result<T, E>;
+
+#[derive(Clone)]
 pub struct FormatOptions {
     /// Number of spaces used for one level of indentation
     pub indentation: usize,
+    /// Add the declarations of well-known prefixes (rdf, xsd, owl...) that are used but not defined
+    pub declare_missing_prefixes: bool,
+    /// Prefixes defined outside of the formatted file (prefix name without `:` -> IRI)
+    ///
+    /// They are used to resolve prefixed names but are not declared in the output.
+    pub extra_prefixes: HashMap<String, String>,
+    /// Fail on language tags that are not well-formed according to BCP 47
+    pub validate_language_tags: bool,
+    /// Rewrite boolean and numeric literals to their XSD canonical lexical form (`+01` -> `1`, `1.0e0` -> `1.0E0`...)
+    ///
+    /// Beware that this changes the lexical form of the literals, hence the RDF terms, even if their value is kept.
+    pub canonicalize_literals: bool,
+    /// How to quote strings
+    pub string_quoting: StringQuoting,
+    /// Which characters to write as `\uXXXX` escapes in strings and IRIs
+    pub escaping: CharacterEscaping,
+    /// What to do with strings, IRIs and prefixed names that are not in Unicode Normalization Form C
+    pub nfc: NfcPolicy,
+    /// Keep the comments text as is instead of normalizing the spaces after `#`
+    ///
+    /// Comments starting with `#!` are always kept as is.
+    pub verbatim_comments: bool,
+    /// Maximal line width: longer block comments are wrapped at word boundaries
+    ///
+    /// Only the comments between statements are wrapped.
+    /// The comments inside of statements and the ones trailing a statement are kept on a single line.
+    pub max_width: Option<usize>,
+    /// Join consecutive block comment lines into paragraphs before wrapping them to `max_width`
+    ///
+    /// Blank comment lines and Markdown-like list items start new paragraphs.
+    pub join_comment_lines: bool,
+    /// Align the comments trailing consecutive lines to the same column
+    pub align_comments: bool,
+    /// Copy the invalid statements as is and report their errors as warnings instead of failing
+    pub keep_invalid_statements: bool,
+    /// Maximal number of errors to report before stopping
+    pub max_errors: NonZeroUsize,
 }
 
 impl Default for FormatOptions {
     fn default() -> Self {
-        Self { indentation: 4 }
+        Self {
+            indentation: 4,
+            declare_missing_prefixes: false,
+            extra_prefixes: HashMap::new(),
+            validate_language_tags: false,
+            canonicalize_literals: false,
+            string_quoting: StringQuoting::default(),
+            escaping: CharacterEscaping::default(),
+            nfc: NfcPolicy::default(),
+            verbatim_comments: false,
+            max_width: None,
+            join_comment_lines: false,
+            align_comments: false,
+            keep_invalid_statements: false,
+            max_errors: NonZeroUsize::new(20).unwrap(),
+        }
+    }
+}
+
+/// What to do with strings, IRIs and prefixed names that are not in [Unicode Normalization Form C](https://unicode.org/reports/tr15/)
+///
+/// Such strings look identical to their NFC form but are different RDF terms.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum NfcPolicy {
+    /// Do not check the normalization form
+    #[default]
+    Ignore,
+    /// Report the strings, IRIs and prefixed names not in NFC as warnings
+    Report,
+    /// Normalize the strings to NFC and report the IRIs and prefixed names not in NFC as warnings
+    ///
+    /// Beware that this changes the literals lexical forms and values.
+    NormalizeLiterals,
+}
+
+/// A non-fatal issue found while formatting
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Warning {
+    /// Line of the issue, starting from 1
+    pub line: usize,
+    /// The location of the issue in the input file
+    pub span: Span,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} on line {}", self.message, self.line)
+    }
+}
+
+/// How strings are quoted
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum StringQuoting {
+    /// Always use `"` and keep long quotes (`"""`) only if the input string used them
+    #[default]
+    Double,
+    /// Use long quotes (`"""`) only for strings containing line breaks
+    /// and use `'` instead of `"` if it avoids escaping quotes
+    Adaptive,
+}
+
+/// Which characters are escaped in strings and IRIs
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum CharacterEscaping {
+    /// Unescape all characters that are allowed to be written as is
+    #[default]
+    Minimal,
+    /// Keep control, format (including zero-width characters) and bidirectional characters escaped
+    ///
+    /// This avoids invisible characters in the output.
+    /// Prefixed names containing such characters are written as full IRIs.
+    Invisible,
+    /// Escape all non-ASCII characters (and the invisible ones)
+    ///
+    /// Prefixed names containing such characters are written as full IRIs.
+    /// Comments, blank node labels and the labels of prefix declarations can't contain escapes and are kept as is.
+    Ascii,
+}
+
+impl CharacterEscaping {
+    fn must_escape(self, c: char) -> bool {
+        match self {
+            Self::Minimal => false,
+            Self::Invisible => is_invisible_char(c),
+            Self::Ascii => !c.is_ascii() || is_invisible_char(c),
+        }
     }
 }
 
@@ -38,40 +178,181 @@ fn get_tree_sitter_turtle() -> Language {
     unsafe { tree_sitter_turtle() }
 }
 
-pub fn format_turtle(original: &str, options: &FormatOptions) -> Result<String> {
-    let mut parser = tree_sitter::Parser::new();
-    parser.set_language(&get_tree_sitter_turtle())?;
-    let tree = parser.parse(original.as_bytes(), None).unwrap();
+/// A formatter keeping its Turtle parser to format many files
+///
+/// Unlike [`format_turtle`] that creates a new parser on each call.
+pub struct Formatter {
+    parser: tree_sitter::Parser,
+}
 
-    let mut formatted = String::new();
-    TurtleFormatter {
-        file: original.as_bytes(),
-        output: &mut formatted,
-        options,
-        prefixes: HashMap::new(),
+impl Formatter {
+    pub fn new() -> Self {
+        let mut parser = tree_sitter::Parser::new();
+        parser
+            .set_language(&get_tree_sitter_turtle())
+            .expect("The Turtle grammar is generated for the tree-sitter version in use");
+        Self { parser }
     }
-    .fmt_doc(tree.root_node())?;
+
+    pub fn format(
+        &mut self,
+        original: &str,
+        options: &FormatOptions,
+    ) -> Result<String, FormatErrors> {
+        Ok(self.format_with_warnings(original, options)?.0)
+    }
+
+    /// Same as [`Formatter::format`] but also returns the warnings found while formatting
+    pub fn format_with_warnings(
+        &mut self,
+        original: &str,
+        options: &FormatOptions,
+    ) -> Result<(String, Vec<Warning>), FormatErrors> {
+        let tree = self.parse(original);
+
+        let mut formatter = TurtleFormatter::new(original, options);
+        let result = formatter.fmt_doc(tree.root_node());
+        formatter.check_errors(result)?;
+        if !formatter.missing_prefixes.is_empty() {
+            // We format again, this time declaring the missing prefixes
+            let missing_prefixes = take(&mut formatter.missing_prefixes);
+            formatter = TurtleFormatter::new(original, options);
+            formatter.prefixes_to_declare = missing_prefixes;
+            let result = formatter.fmt_doc(tree.root_node());
+            formatter.check_errors(result)?;
+        }
+        Ok((formatter.output, formatter.warnings))
+    }
+
+    fn parse(&mut self, original: &str) -> Tree {
+        // There is no timeout or cancellation flag so the parsing always returns a tree
+        self.parser.parse(original.as_bytes(), None).unwrap()
+    }
+}
+
+impl Default for Formatter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn format_turtle(original: &str, options: &FormatOptions) -> Result<String, FormatErrors> {
+    Formatter::new().format(original, options)
+}
+
+/// Same as [`format_turtle`] but also returns the warnings found while formatting
+pub fn format_turtle_with_warnings(
+    original: &str,
+    options: &FormatOptions,
+) -> Result<(String, Vec<Warning>), FormatErrors> {
+    Formatter::new().format_with_warnings(original, options)
+}
+
+/// Formats only the statements overlapping the byte range `range` of `original`
+///
+/// The rest of the document is kept byte-identical.
+/// The prefixes declared in the whole document are used but missing prefixes are never declared.
+pub fn format_turtle_range(
+    original: &str,
+    range: Range<usize>,
+    options: &FormatOptions,
+) -> Result<String, FormatErrors> {
+    if range.start > range.end || range.end > original.len() {
+        return Err(FormatError::InvalidRange {
+            len: original.len(),
+            range,
+        }
+        .into());
+    }
+    let tree = Formatter::new().parse(original);
+
+    let options = FormatOptions {
+        declare_missing_prefixes: false,
+        ..options.clone()
+    };
+    let mut formatter = TurtleFormatter::new(original, &options);
+    let mut replacements = Vec::new();
+    let result = formatter.fmt_doc_range(tree.root_node(), &range, &mut replacements);
+    formatter.check_errors(result)?;
+    let mut formatted = String::with_capacity(original.len());
+    let mut end = 0;
+    for (statement, replacement) in replacements {
+        formatted.push_str(&original[end..statement.start]);
+        formatted.push_str(&replacement);
+        end = statement.end;
+    }
+    formatted.push_str(&original[end..]);
     Ok(formatted)
 }
 
-struct TurtleFormatter<'a, W: Write> {
+/// Returns the prefixes declared in a Turtle file
+///
+/// This is useful to build [`FormatOptions::extra_prefixes`] from a shared header file.
+pub fn extract_prefixes(turtle: &str) -> Result<HashMap<String, String>, FormatErrors> {
+    let tree = Formatter::new().parse(turtle);
+    let options = FormatOptions::default();
+    let mut formatter = TurtleFormatter::new(turtle, &options);
+    let result = formatter.fmt_doc(tree.root_node());
+    formatter.check_errors(result)?;
+    Ok(formatter.prefixes)
+}
+
+struct TurtleFormatter<'a> {
     file: &'a [u8],
-    output: W,
+    output: String,
     options: &'a FormatOptions,
     prefixes: HashMap<String, String>,
+    /// Well-known prefixes used without being declared
+    missing_prefixes: BTreeMap<String, String>,
+    /// Prefixes to declare in the first prefix block
+    prefixes_to_declare: BTreeMap<String, String>,
+    warnings: Vec<Warning>,
+    /// Errors found in the statements, formatting goes on to report them all
+    errors: Vec<FormatError>,
+    /// Comments inside of the current statement not written yet, with if they are trailing a token
+    pending_comments: Vec<(&'a str, bool)>,
+    /// Positions in the output of the comments written after some code
+    trailing_comments: Vec<usize>,
 }
 
-impl<W: Write> TurtleFormatter<'_, W> {
+impl<'a> TurtleFormatter<'a> {
+    fn new(file: &'a str, options: &'a FormatOptions) -> Self {
+        Self {
+            file: file.as_bytes(),
+            output: String::new(),
+            options,
+            prefixes: options.extra_prefixes.clone(),
+            missing_prefixes: BTreeMap::new(),
+            prefixes_to_declare: BTreeMap::new(),
+            warnings: Vec::new(),
+            errors: Vec::new(),
+            pending_comments: Vec::new(),
+            trailing_comments: Vec::new(),
+        }
+    }
+
     fn fmt_doc(&mut self, node: Node<'_>) -> Result<()> {
         debug_assert_eq!(node.kind(), "turtle_doc");
 
         let mut context = RootContext::Start;
         let mut row = node.start_position().row;
         let mut prefix_buffer: Vec<(Node<'_>, Vec<Node<'_>>)> = Vec::new();
-        for child in Self::iter_children(node)? {
+        let mut walk = node.walk();
+        let mut children = node
+            .children(&mut walk)
+            .filter(|child| child.is_named() || child.is_error() || child.is_missing())
+            .peekable();
+        let mut skip_next = false;
+        while let Some(mut child) = children.next() {
+            if self.errors.len() >= self.options.max_errors.get() {
+                break;
+            }
+            let is_invalid = child.has_error();
             match child.kind() {
                 "comment" => {
-                    if child.start_position().row == row {
+                    if child.start_position().row == row
+                        && (context != RootContext::Start || !prefix_buffer.is_empty())
+                    {
                         if let Some((_, prefix_comments)) = prefix_buffer.last_mut() {
                             // We keep the comment connected to the prefixes
                             prefix_comments.push(child);
@@ -84,7 +365,7 @@ impl<W: Write> TurtleFormatter<'_, W> {
                         }
                     } else {
                         // Block comment
-                        self.fmt_possible_prefixes(&mut prefix_buffer, &mut context)?;
+                        self.fmt_possible_prefixes(&mut prefix_buffer, &mut context, false)?;
                         if context != RootContext::Start {
                             for _ in 0..(child.start_position().row - row).clamp(
                                 if context == RootContext::Comment {
@@ -97,12 +378,58 @@ impl<W: Write> TurtleFormatter<'_, W> {
                                 writeln!(self.output)?;
                             }
                         }
-                        self.fmt_comments([child], false)?;
+                        if self.comment_directive(child) == Some("off") {
+                            // We copy everything until the "on" marker as is
+                            let start = child.start_byte();
+                            for next in children.by_ref() {
+                                if next.kind() == "prefix" {
+                                    // The prefix is still usable by the formatted statements
+                                    self.register_prefix(next)?;
+                                }
+                                child = next;
+                                if next.kind() == "comment"
+                                    && self.comment_directive(next) == Some("on")
+                                {
+                                    break;
+                                }
+                            }
+                            self.fmt_raw(start..child.end_byte())?;
+                        } else {
+                            // We format together the comments on consecutive lines
+                            let mut block = vec![child];
+                            while let Some(next) = children.next_if(|next| {
+                                next.kind() == "comment"
+                                    && next.start_position().row == child.end_position().row + 1
+                                    && self.comment_directive(*next) != Some("off")
+                            }) {
+                                block.push(next);
+                                child = next;
+                            }
+                            self.fmt_comment_block(&block)?;
+                            skip_next = block
+                                .iter()
+                                .any(|c| self.comment_directive(*c) == Some("skip"));
+                        }
                         context = RootContext::Comment;
                     }
                 }
-                "base" => {
-                    self.fmt_possible_prefixes(&mut prefix_buffer, &mut context)?;
+                "base" | "prefix" if skip_next && !is_invalid => {
+                    self.fmt_possible_prefixes(&mut prefix_buffer, &mut context, false)?;
+                    if context != RootContext::Start {
+                        writeln!(self.output)?;
+                    }
+                    if context == RootContext::Triples {
+                        writeln!(self.output)?;
+                    }
+                    if child.kind() == "prefix" {
+                        self.register_prefix(child)?;
+                    }
+                    self.fmt_raw(child.byte_range())?;
+                    context = RootContext::Prefixes;
+                    skip_next = false;
+                }
+                "base" if !is_invalid => {
+                    self.fmt_possible_prefixes(&mut prefix_buffer, &mut context, false)?;
                     if context != RootContext::Start {
                         writeln!(self.output)?;
                     }
@@ -110,37 +437,170 @@ impl<W: Write> TurtleFormatter<'_, W> {
                         writeln!(self.output)?;
                     }
                     context = RootContext::Prefixes;
-                    self.fmt_base(child)?;
+                    self.fmt_statement(child, Self::fmt_base)?;
                 }
-                "prefix" => {
+                "prefix" if !is_invalid => {
                     prefix_buffer.push((child, Vec::new()));
                 }
-                "triples" => {
-                    self.fmt_possible_prefixes(&mut prefix_buffer, &mut context)?;
+                kind if kind == "triples" || is_invalid => {
+                    self.fmt_possible_prefixes(&mut prefix_buffer, &mut context, true)?;
                     if context != RootContext::Start {
                         if context != RootContext::Comment || child.start_position().row > row + 1 {
                             writeln!(self.output)?;
                         }
                         writeln!(self.output)?;
                     }
-                    self.fmt_triples(child)?;
+                    if is_invalid {
+                        self.invalid_statement(child)?;
+                    } else if skip_next {
+                        self.fmt_raw(child.byte_range())?;
+                    } else {
+                        self.fmt_statement(child, Self::fmt_triples)?;
+                    }
+                    skip_next = false;
                     context = RootContext::Triples;
                 }
-                _ => bail!("Unexpected turtle_doc child: {}", child.to_sexp()),
+                _ => return Err(Self::unexpected("Unexpected turtle_doc child", child)),
             }
             row = child.end_position().row;
         }
-        self.fmt_possible_prefixes(&mut prefix_buffer, &mut context)?;
+        self.fmt_possible_prefixes(&mut prefix_buffer, &mut context, true)?;
         writeln!(self.output)?;
+        if self.options.align_comments {
+            self.align_trailing_comments();
+        }
         Ok(())
     }
 
+    /// Formats the statements overlapping the range and adds them with their byte range in the file to `replacements`
+    ///
+    /// Only the errors of the statements overlapping the range are reported.
+    fn fmt_doc_range(
+        &mut self,
+        node: Node<'_>,
+        range: &Range<usize>,
+        replacements: &mut Vec<(Range<usize>, String)>,
+    ) -> Result<()> {
+        debug_assert_eq!(node.kind(), "turtle_doc");
+
+        // The directives are read the same way as `fmt_doc`: only from the block comments
+        let mut is_off = false;
+        let mut skip_next = false;
+        let mut is_start = true;
+        let mut row = node.start_position().row;
+        let mut previous_comment_row = None;
+        let mut walk = node.walk();
+        for child in node
+            .children(&mut walk)
+            .filter(|child| child.is_named() || child.is_error() || child.is_missing())
+        {
+            let is_invalid = child.has_error();
+            let is_in_range = if range.is_empty() {
+                child.byte_range().contains(&range.start) || child.end_byte() == range.start
+            } else {
+                child.start_byte() < range.end && range.start < child.end_byte()
+            };
+            match child.kind() {
+                "comment" => {
+                    let directive = self.comment_directive(child);
+                    if is_off {
+                        is_off = directive != Some("on");
+                    } else if is_start || child.start_position().row != row {
+                        // A new block of comments resets the skip directive
+                        if previous_comment_row.map_or(true, |r| child.start_position().row > r + 1)
+                        {
+                            skip_next = false;
+                        }
+                        match directive {
+                            Some("off") => is_off = true,
+                            Some("skip") => skip_next = true,
+                            _ => (),
+                        }
+                        previous_comment_row = Some(child.end_position().row);
+                        is_start = false;
+                    }
+                    row = child.end_position().row;
+                    continue;
+                }
+                "base" | "prefix" | "triples" => (),
+                _ if is_invalid => (),
+                _ => return Err(Self::unexpected("Unexpected turtle_doc child", child)),
+            }
+            is_start = false;
+            row = child.end_position().row;
+            previous_comment_row = None;
+            if (take(&mut skip_next) && !is_invalid) || is_off || !is_in_range {
+                if child.kind() == "prefix" && !is_invalid {
+                    // The errors of the declaration are only reported if it is formatted
+                    self.register_prefix(child).ok();
+                }
+                continue;
+            }
+            if is_invalid {
+                self.invalid_statement(child)?;
+            } else {
+                match child.kind() {
+                    "prefix" => self.fmt_statement(child, Self::fmt_prefix)?,
+                    "base" => self.fmt_statement(child, Self::fmt_base)?,
+                    _ => self.fmt_statement(child, Self::fmt_triples)?,
+                }
+            }
+            if self.options.align_comments {
+                self.align_trailing_comments();
+            }
+            self.trailing_comments.clear();
+            replacements.push((child.byte_range(), take(&mut self.output)));
+        }
+        Ok(())
+    }
+
+    /// Aligns the comments trailing consecutive lines to the same column
+    fn align_trailing_comments(&mut self) {
+        // We compute for each trailing comment its line and its column
+        let mut comments = Vec::with_capacity(self.trailing_comments.len());
+        let mut line = 0;
+        let mut line_start = 0;
+        for &position in &self.trailing_comments {
+            let text = &self.output[line_start..position];
+            line += text.matches('\n').count();
+            if let Some(i) = text.rfind('\n') {
+                line_start += i + 1;
+            }
+            let column = self.output[line_start..position].chars().count();
+            comments.push((position, line, column));
+        }
+
+        // We compute the padding of each comment
+        let mut paddings = Vec::with_capacity(comments.len());
+        for group in comments.chunk_by(|(_, l1, _), (_, l2, _)| l1 + 1 == *l2) {
+            let max_column = group.iter().map(|(_, _, c)| *c).max().unwrap_or(0);
+            for (position, _, column) in group {
+                paddings.push((*position, max_column - column));
+            }
+        }
+
+        let mut aligned = String::with_capacity(self.output.len());
+        let mut start = 0;
+        for (position, padding) in paddings {
+            aligned.push_str(&self.output[start..position]);
+            aligned.extend((0..padding).map(|_| ' '));
+            start = position;
+        }
+        aligned.push_str(&self.output[start..]);
+        self.output = aligned;
+    }
+
+    /// Formats the buffered prefix declarations
+    ///
+    /// The missing prefixes to declare are added to the first block of prefixes
+    /// or, if there are none, just before the first triples.
     fn fmt_possible_prefixes(
         &mut self,
         nodes: &mut Vec<(Node<'_>, Vec<Node<'_>>)>,
         context: &mut RootContext,
+        is_before_triples: bool,
     ) -> Result<()> {
-        if nodes.is_empty() {
+        if nodes.is_empty() && (!is_before_triples || self.prefixes_to_declare.is_empty()) {
             return Ok(());
         }
         if *context != RootContext::Start {
@@ -153,13 +613,37 @@ impl<W: Write> TurtleFormatter<'_, W> {
             node.child_by_field_name("label")
                 .map_or("", |n| n.utf8_text(self.file).unwrap_or(""))
         });
-        for (i, (node, comments)) in nodes.iter().enumerate() {
-            if i > 0 {
+        let mut to_declare = take(&mut self.prefixes_to_declare).into_iter().peekable();
+        let mut is_first = true;
+        for (node, comments) in nodes.iter() {
+            debug_assert_eq!(node.kind(), "prefix");
+            let label = node
+                .child_by_field_name("label")
+                .map_or("", |n| n.utf8_text(self.file).unwrap_or(""));
+            while let Some((prefix, iri)) =
+                to_declare.next_if(|(prefix, _)| prefix.as_str() < label)
+            {
+                if !is_first {
+                    writeln!(self.output)?;
+                }
+                self.fmt_prefix_declaration(prefix, iri)?;
+                write!(self.output, " .")?;
+                is_first = false;
+            }
+            if !is_first {
                 writeln!(self.output)?;
             }
-            debug_assert_eq!(node.kind(), "prefix");
-            self.fmt_prefix(*node)?;
+            self.fmt_statement(*node, Self::fmt_prefix)?;
             self.fmt_comments(comments.iter().copied(), true)?;
+            is_first = false;
+        }
+        for (prefix, iri) in to_declare {
+            if !is_first {
+                writeln!(self.output)?;
+            }
+            self.fmt_prefix_declaration(prefix, iri)?;
+            write!(self.output, " .")?;
+            is_first = false;
         }
         nodes.clear();
         *context = RootContext::Prefixes;
@@ -174,9 +658,10 @@ impl<W: Write> TurtleFormatter<'_, W> {
                 "comment" => comments.push(child),
                 "iriref" => {
                     let iri = self.extract_iriref(child)?;
-                    write!(self.output, "@base <{iri}>")?;
+                    write!(self.output, "@base ")?;
+                    self.fmt_iri(&iri)?;
                 }
-                _ => bail!("Unexpected base child: {}", child.to_sexp()),
+                _ => return Err(Self::unexpected("Unexpected base child", child)),
             }
         }
         write!(self.output, " .")?;
@@ -195,16 +680,113 @@ impl<W: Write> TurtleFormatter<'_, W> {
                 }
                 "iriref" => {
                     let iri = self.extract_iriref(child)?;
-                    write!(self.output, "@prefix {prefix}: <{iri}>")?;
-                    self.prefixes.insert(prefix.to_string(), iri);
+                    self.fmt_prefix_declaration(prefix.to_string(), iri)?;
                 }
-                _ => bail!("Unexpected prefix child: {}", child.to_sexp()),
+                _ => return Err(Self::unexpected("Unexpected prefix child", child)),
             }
         }
         write!(self.output, " .")?;
         self.fmt_comments(comments, true)
     }
 
+    /// Registers the prefix declared by the node without formatting it
+    ///
+    /// The errors in the declaration are not reported because it is not formatted.
+    fn register_prefix(&mut self, node: Node<'_>) -> Result<()> {
+        debug_assert_eq!(node.kind(), "prefix");
+        let errors_len = self.errors.len();
+        let mut prefix = "";
+        for child in Self::iter_children(node)? {
+            match child.kind() {
+                "comment" => (),
+                "pn_prefix" => {
+                    prefix = child.utf8_text(self.file)?;
+                }
+                "iriref" => {
+                    let iri = self.extract_iriref(child)?;
+                    self.prefixes.insert(prefix.to_string(), iri);
+                }
+                _ => return Err(Self::unexpected("Unexpected prefix child", child)),
+            }
+        }
+        self.errors.truncate(errors_len);
+        Ok(())
+    }
+
+    /// Formats the statement or, if it fails, records the error and goes on with the next statements
+    fn fmt_statement(
+        &mut self,
+        node: Node<'_>,
+        fmt: impl FnOnce(&mut Self, Node<'_>) -> Result<()>,
+    ) -> Result<()> {
+        let output_len = self.output.len();
+        let trailing_comments_len = self.trailing_comments.len();
+        let errors_len = self.errors.len();
+        let result = fmt(self, node);
+        if result.is_err() || self.errors.len() > errors_len {
+            self.output.truncate(output_len);
+            self.trailing_comments.truncate(trailing_comments_len);
+            self.pending_comments.clear();
+            let mut errors = self.errors.split_off(errors_len);
+            errors.extend(result.err());
+            self.invalid_statement_errors(node, errors)?;
+        }
+        Ok(())
+    }
+
+    /// Records the syntax errors of a statement
+    fn invalid_statement(&mut self, node: Node<'_>) -> Result<()> {
+        if self.options.keep_invalid_statements {
+            return self
+                .invalid_statement_errors(node, vec![Self::syntax_error(Self::first_error(node))]);
+        }
+        let mut errors = Vec::new();
+        Self::collect_errors(node, &mut errors);
+        self.errors
+            .extend(errors.into_iter().map(Self::syntax_error));
+        Ok(())
+    }
+
+    /// Records the errors or, in the tolerant mode, copies the statement as is and reports the first error as a warning
+    fn invalid_statement_errors(&mut self, node: Node<'_>, errors: Vec<FormatError>) -> Result<()> {
+        if self.options.keep_invalid_statements {
+            self.fmt_raw(node.byte_range())?;
+            if let Some(error) = errors.first() {
+                self.warn(node, format!("Invalid statement kept as is ({error})"));
+            }
+        } else {
+            self.errors.extend(errors);
+        }
+        Ok(())
+    }
+
+    /// Fails with all the errors found if there are some
+    fn check_errors(&mut self, result: Result<()>) -> Result<(), FormatErrors> {
+        let mut errors = take(&mut self.errors);
+        if let Err(error) = result {
+            errors.push(error);
+        }
+        if errors.is_empty() {
+            return Ok(());
+        }
+        errors.truncate(self.options.max_errors.get());
+        Err(FormatErrors(errors))
+    }
+
+    /// Copies a part of the input file as is
+    fn fmt_raw(&mut self, range: Range<usize>) -> Result<()> {
+        self.output
+            .push_str(std::str::from_utf8(&self.file[range])?.trim_end());
+        Ok(())
+    }
+
+    fn fmt_prefix_declaration(&mut self, prefix: String, iri: String) -> Result<()> {
+        write!(self.output, "@prefix {prefix}: ")?;
+        self.fmt_iri(&iri)?;
+        self.prefixes.insert(prefix, iri);
+        Ok(())
+    }
+
     fn new_indented_line(&mut self, indents: usize) -> Result<()> {
         writeln!(self.output)?;
         for _ in 0..(self.options.indentation * indents) {
@@ -213,57 +795,127 @@ impl<W: Write> TurtleFormatter<'_, W> {
         Ok(())
     }
 
+    /// Adds a comment found inside of a statement to the comments to write before the next term
+    fn push_comment(&mut self, node: Node<'_>) -> Result<()> {
+        debug_assert_eq!(node.kind(), "comment");
+        // The comment is trailing if there is something before it on its line
+        let is_trailing = self.file[..node.start_byte()]
+            .iter()
+            .rev()
+            .find(|c| !matches!(c, b' ' | b'\t'))
+            .is_some_and(|c| !matches!(c, b'\n' | b'\r'));
+        self.pending_comments
+            .push((node.utf8_text(self.file)?, is_trailing));
+        Ok(())
+    }
+
+    /// Writes the pending comments, the first one on the current line if it was a trailing comment
+    ///
+    /// Returns if some comments have been written
+    fn fmt_pending_comments(&mut self, indent: usize) -> Result<bool> {
+        let comments = take(&mut self.pending_comments);
+        for (i, (comment, is_trailing)) in comments.iter().enumerate() {
+            if i == 0 && *is_trailing {
+                self.trailing_comments.push(self.output.len());
+                write!(self.output, " ")?;
+            } else {
+                self.new_indented_line(indent)?;
+            }
+            self.fmt_comment(comment)?;
+        }
+        Ok(!comments.is_empty())
+    }
+
+    /// Writes the pending comments then a space if `inline` and there were no comments, else a new line
+    ///
+    /// Returns if a new line has been started
+    fn fmt_break(&mut self, inline: bool, indent: usize) -> Result<bool> {
+        if self.fmt_pending_comments(indent)? || !inline {
+            self.new_indented_line(indent)?;
+            Ok(true)
+        } else {
+            write!(self.output, " ")?;
+            Ok(false)
+        }
+    }
+
+    /// Writes the closing token of a blank node property list or a collection
+    fn fmt_closing(&mut self, token: &str, indent: usize) -> Result<()> {
+        if self.fmt_pending_comments(indent + 1)? {
+            self.new_indented_line(indent)?;
+        } else {
+            write!(self.output, " ")?;
+        }
+        write!(self.output, "{token}")?;
+        Ok(())
+    }
+
     fn fmt_triples(&mut self, node: Node<'_>) -> Result<()> {
         debug_assert_eq!(node.kind(), "triples");
-        let mut comments = Vec::new();
         let mut is_first_predicate_objects = true;
         for child in Self::iter_children(node)? {
             match child.kind() {
-                "comment" => comments.push(child),
+                "comment" => self.push_comment(child)?,
                 "predicate_objects" => {
                     if is_first_predicate_objects {
-                        write!(self.output, " ")?;
+                        self.fmt_predicate_objects(child, 1, true)?;
                         is_first_predicate_objects = false;
                     } else {
                         write!(self.output, " ;")?;
-                        self.fmt_comments(comments.drain(0..), true)?;
-                        self.new_indented_line(1)?;
+                        self.fmt_predicate_objects(child, 1, false)?;
                     }
-                    self.fmt_predicate_objects(child, &mut comments)?;
                 }
                 _ => {
                     // The subject
-                    self.fmt_term(child, &mut comments, false)?;
+                    self.fmt_term(child, false, 0)?;
                 }
             }
         }
-        write!(self.output, " .")?;
-        self.fmt_comments(comments, true)
+        if self
+            .pending_comments
+            .iter()
+            .enumerate()
+            .any(|(i, (_, is_trailing))| i > 0 || !is_trailing)
+        {
+            // We keep the comments on their own lines before the end of the statement
+            self.fmt_pending_comments(1)?;
+            self.new_indented_line(1)?;
+            write!(self.output, ".")?;
+        } else {
+            write!(self.output, " .")?;
+            self.fmt_pending_comments(0)?;
+        }
+        Ok(())
     }
 
-    fn fmt_predicate_objects<'b>(
-        &mut self,
-        node: Node<'b>,
-        comments: &mut Vec<Node<'b>>,
-    ) -> Result<()> {
+    /// Formats the predicate and its objects
+    ///
+    /// The predicate is written on a new line if `inline` is false or if comments are written before it.
+    fn fmt_predicate_objects(&mut self, node: Node<'_>, indent: usize, inline: bool) -> Result<()> {
         debug_assert_eq!(node.kind(), "predicate_objects");
         let mut is_predicate = true;
         let mut is_first_object = true;
         for child in Self::iter_children(node)? {
             match child.kind() {
-                "comment" => comments.push(child),
+                "comment" => self.push_comment(child)?,
                 _ => {
                     if is_predicate {
-                        self.fmt_term(child, comments, true)?;
+                        self.fmt_break(inline, indent)?;
+                        self.fmt_term(child, true, indent)?;
                         is_predicate = false;
                     } else {
                         if is_first_object {
-                            write!(self.output, " ")?;
                             is_first_object = false;
                         } else {
-                            write!(self.output, " , ")?;
+                            write!(self.output, " ,")?;
                         }
-                        self.fmt_term(child, comments, false)?;
+                        // The nested terms are indented relatively to the line the object starts on
+                        let object_indent = if self.fmt_break(true, indent + 1)? {
+                            indent + 1
+                        } else {
+                            indent
+                        };
+                        self.fmt_term(child, false, object_indent)?;
                     }
                 }
             }
@@ -271,12 +923,8 @@ impl<W: Write> TurtleFormatter<'_, W> {
         Ok(())
     }
 
-    fn fmt_term<'b>(
-        &mut self,
-        node: Node<'b>,
-        comments: &mut Vec<Node<'b>>,
-        is_predicate: bool,
-    ) -> Result<()> {
+    /// Formats a term, `indent` being the indentation level of the line the term starts on
+    fn fmt_term(&mut self, node: Node<'_>, is_predicate: bool, indent: usize) -> Result<()> {
         enum LiteralAnnotation {
             None,
             LangTag(String),
@@ -288,18 +936,18 @@ impl<W: Write> TurtleFormatter<'_, W> {
             "iriref" => {
                 let iri = self.extract_iriref(node)?;
                 if is_predicate && iri == "http://www.w3.org/1999/02/22-rdf-syntax-ns#type" {
-                    write!(self.output, "a")
+                    write!(self.output, "a")?;
                 } else {
-                    write!(self.output, "<{iri}>")
-                }?;
+                    self.fmt_iri(&iri)?;
+                }
             }
             "prefixed_name" => {
                 let ((prefix, local), iri) = self.extract_prefixed_name(node)?;
                 if is_predicate && iri == "http://www.w3.org/1999/02/22-rdf-syntax-ns#type" {
-                    write!(self.output, "a")
+                    write!(self.output, "a")?;
                 } else {
-                    write!(self.output, "{prefix}:{local}")
-                }?;
+                    self.fmt_prefixed_name(&prefix, &local, &iri)?;
+                }
             }
             "a" => write!(self.output, "a")?,
             "anon" => write!(self.output, "[]")?,
@@ -309,32 +957,35 @@ impl<W: Write> TurtleFormatter<'_, W> {
                 write!(self.output, "[")?;
                 for child in Self::iter_children(node)? {
                     match child.kind() {
-                        "comment" => comments.push(child),
+                        "comment" => self.push_comment(child)?,
                         _ => {
                             if is_first_predicate_objects {
-                                write!(self.output, " ")?;
                                 is_first_predicate_objects = false;
                             } else {
-                                write!(self.output, " ; ")?;
+                                write!(self.output, " ;")?;
                             }
-                            self.fmt_predicate_objects(child, comments)?;
+                            self.fmt_predicate_objects(child, indent + 1, true)?;
                         }
                     }
                 }
-                write!(self.output, " ]")?;
+                self.fmt_closing("]", indent)?;
             }
             "collection" => {
                 write!(self.output, "(")?;
                 for child in Self::iter_children(node)? {
                     match child.kind() {
-                        "comment" => comments.push(child),
+                        "comment" => self.push_comment(child)?,
                         _ => {
-                            write!(self.output, " ")?;
-                            self.fmt_term(child, comments, false)?;
+                            let item_indent = if self.fmt_break(true, indent + 1)? {
+                                indent + 1
+                            } else {
+                                indent
+                            };
+                            self.fmt_term(child, false, item_indent)?;
                         }
                     }
                 }
-                write!(self.output, " )")?;
+                self.fmt_closing(")", indent)?;
             }
             "literal" => {
                 let mut value = String::new();
@@ -343,11 +994,10 @@ impl<W: Write> TurtleFormatter<'_, W> {
                 let mut datatype = Cow::Borrowed("http://www.w3.org/2001/XMLSchema#string");
                 for child in Self::iter_children(node)? {
                     match child.kind() {
-                        "comment" => comments.push(child),
+                        "comment" => self.push_comment(child)?,
                         "string" => (value, is_long_string) = self.extract_string(child)?,
                         "langtag" => {
-                            annotation =
-                                LiteralAnnotation::LangTag(child.utf8_text(self.file)?.to_string());
+                            annotation = LiteralAnnotation::LangTag(self.extract_langtag(child)?);
                             datatype =
                                 "http://www.w3.org/1999/02/22-rdf-syntax-ns#langString".into();
                         }
@@ -363,45 +1013,50 @@ impl<W: Write> TurtleFormatter<'_, W> {
                             datatype = resolved_iri.into();
                         }
                         "@" | "^^" | "<" | ">" => (),
-                        _ => bail!("Unexpected literal child: {}", child.to_sexp()),
+                        _ => return Err(Self::unexpected("Unexpected literal child", child)),
+                    }
+                }
+                if self.options.canonicalize_literals {
+                    if let Some(canonical) = canonical_lexical_form(&datatype, &value) {
+                        value = canonical;
                     }
                 }
                 match datatype.as_ref() {
                     "http://www.w3.org/2001/XMLSchema#boolean"
                         if matches!(value.as_str(), "true" | "false") =>
                     {
-                        write!(self.output, "{value}")
+                        write!(self.output, "{value}")?
                     }
                     "http://www.w3.org/2001/XMLSchema#integer" if is_turtle_integer(&value) => {
-                        write!(self.output, "{value}")
+                        write!(self.output, "{value}")?
                     }
                     "http://www.w3.org/2001/XMLSchema#decimal" if is_turtle_decimal(&value) => {
-                        write!(self.output, "{value}")
+                        write!(self.output, "{value}")?
                     }
                     "http://www.w3.org/2001/XMLSchema#double" if is_turtle_double(&value) => {
-                        write!(self.output, "{value}")
+                        write!(self.output, "{value}")?
                     }
                     _ => {
-                        if is_long_string {
-                            write!(self.output, "\"\"\"{value}\"\"\"")?;
-                        } else {
-                            write!(self.output, "\"{value}\"")?;
-                        }
+                        self.fmt_string(&value, is_long_string)?;
                         match annotation {
-                            LiteralAnnotation::None => Ok(()),
-                            LiteralAnnotation::LangTag(l) => write!(self.output, "@{l}"),
-                            LiteralAnnotation::IriRef(i) => write!(self.output, "^^<{i}>"),
+                            LiteralAnnotation::None => (),
+                            LiteralAnnotation::LangTag(l) => write!(self.output, "@{l}")?,
+                            LiteralAnnotation::IriRef(i) => {
+                                write!(self.output, "^^")?;
+                                self.fmt_iri(&i)?;
+                            }
                             LiteralAnnotation::PrefixedName(prefix, local) => {
-                                write!(self.output, "^^{prefix}:{local}")
+                                write!(self.output, "^^")?;
+                                self.fmt_prefixed_name(&prefix, &local, &datatype)?;
                             }
                         }
                     }
-                }?;
+                }
             }
             "integer" => {
                 let value = node.utf8_text(self.file)?;
                 debug_assert!(is_turtle_integer(value), "{value} should be an integer");
-                write!(self.output, "{value}")?
+                self.fmt_numeric(value, "http://www.w3.org/2001/XMLSchema#integer")?
             }
             "boolean" => {
                 let value = node.utf8_text(self.file)?;
@@ -414,14 +1069,61 @@ impl<W: Write> TurtleFormatter<'_, W> {
             "decimal" => {
                 let value = node.utf8_text(self.file)?;
                 debug_assert!(is_turtle_decimal(value), "{value} should be a decimal");
-                write!(self.output, "{value}")?
+                self.fmt_numeric(value, "http://www.w3.org/2001/XMLSchema#decimal")?
             }
             "double" => {
                 let value = node.utf8_text(self.file)?;
                 debug_assert!(is_turtle_double(value), "{value} should be a double");
-                write!(self.output, "{value}")?
+                self.fmt_numeric(value, "http://www.w3.org/2001/XMLSchema#double")?
+            }
+            _ => return Err(Self::unexpected("Unexpected term", node)),
+        }
+        Ok(())
+    }
+
+    fn fmt_numeric(&mut self, value: &str, datatype: &str) -> Result<()> {
+        if self.options.canonicalize_literals {
+            if let Some(canonical) = canonical_lexical_form(datatype, value) {
+                write!(self.output, "{canonical}")?;
+                return Ok(());
+            }
+        }
+        write!(self.output, "{value}")?;
+        Ok(())
+    }
+
+    fn fmt_iri(&mut self, iri: &str) -> Result<()> {
+        write!(self.output, "<")?;
+        for c in iri.chars() {
+            if self.options.escaping.must_escape(c) {
+                self.fmt_uchar(c)?;
+            } else {
+                write!(self.output, "{c}")?;
             }
-            _ => bail!("Unexpected term: {}", node.to_sexp()),
+        }
+        write!(self.output, ">")?;
+        Ok(())
+    }
+
+    /// Writes a prefixed name, or the full IRI if it contains characters that must be escaped
+    fn fmt_prefixed_name(&mut self, prefix: &str, local: &str, iri: &str) -> Result<()> {
+        if prefix
+            .chars()
+            .chain(local.chars())
+            .any(|c| self.options.escaping.must_escape(c))
+        {
+            self.fmt_iri(iri)
+        } else {
+            write!(self.output, "{prefix}:{local}")?;
+            Ok(())
+        }
+    }
+
+    fn fmt_uchar(&mut self, c: char) -> Result<()> {
+        if u32::from(c) <= 0xFFFF {
+            write!(self.output, "\\u{:04X}", u32::from(c))?;
+        } else {
+            write!(self.output, "\\U{:08X}", u32::from(c))?;
         }
         Ok(())
     }
@@ -431,29 +1133,93 @@ impl<W: Write> TurtleFormatter<'_, W> {
         // We normalize the IRI
         let raw = node.utf8_text(self.file)?;
         let mut normalized = String::with_capacity(raw.len());
-        for c in StringDecoder::new(raw) {
-            match c? {
-                c @ ('\x00'..='\x20' | '<' | '>' | '"' | '{' | '}' | '|' | '^' | '`' | '\\') => {
-                    bail!("The character '{c:?} is not allowed in IRIs")
+        for (range, c) in StringDecoder::new(raw) {
+            // We record the errors and go on to report the next ones
+            match self.decoded_char(node.start_byte(), range.clone(), c) {
+                Ok(
+                    c @ ('\x00'..='\x20' | '<' | '>' | '"' | '{' | '}' | '|' | '^' | '`' | '\\'),
+                ) => {
+                    self.errors.push(FormatError::InvalidIriCharacter {
+                        span: self.span(node.start_byte(), range),
+                        character: c,
+                    });
                 }
-                c => normalized.push(c),
+                Ok(c) => normalized.push(c),
+                Err(error) => self.errors.push(error),
             }
         }
+        if self.options.nfc != NfcPolicy::Ignore && !is_nfc(&normalized) {
+            self.warn(
+                node,
+                format!("The IRI <{normalized}> is not in Unicode NFC"),
+            );
+        }
         Ok(normalized)
     }
 
+    /// Converts a decoding error of the escape sequence at `range` after the byte `start` of the file
+    fn decoded_char(
+        &self,
+        start: usize,
+        range: Range<usize>,
+        c: Result<char, String>,
+    ) -> Result<char> {
+        c.map_err(|message| FormatError::InvalidEscape {
+            span: self.span(start, range),
+            message,
+        })
+    }
+
+    /// The span of the byte range `range` relative to the byte `start` of the file
+    fn span(&self, start: usize, range: Range<usize>) -> Span {
+        Span::new(self.file, start + range.start..start + range.end)
+    }
+
+    fn extract_langtag(&mut self, node: Node<'_>) -> Result<String> {
+        debug_assert_eq!(node.kind(), "langtag");
+        let tag = node.utf8_text(self.file)?;
+        if self.options.validate_language_tags {
+            if let Err(error) = validate_language_tag(tag) {
+                self.errors.push(FormatError::InvalidLanguageTag {
+                    span: self.span(node.start_byte(), error.range),
+                    tag: tag.into(),
+                    message: error.message,
+                });
+            }
+        }
+        Ok(normalize_language_tag(tag))
+    }
+
     fn extract_prefixed_name(&mut self, node: Node<'_>) -> Result<((String, String), String)> {
-        let (prefix, local) = node.utf8_text(self.file)?.split_once(':').unwrap();
-        let Some(prefix_value) = self.prefixes.get(prefix) else {
-            bail!(
-                "The prefix {prefix}: is not defined on line {}",
-                node.start_position().row + 1
+        let name = node.utf8_text(self.file)?;
+        if self.options.nfc != NfcPolicy::Ignore && !is_nfc(name) {
+            self.warn(
+                node,
+                format!("The prefixed name {name} is not in Unicode NFC"),
             );
+        }
+        let (prefix, local) = name.split_once(':').unwrap();
+        if self.options.declare_missing_prefixes && !self.prefixes.contains_key(prefix) {
+            if let Some(iri) = well_known_prefix(prefix) {
+                self.prefixes.insert(prefix.to_string(), iri.to_string());
+                self.missing_prefixes
+                    .insert(prefix.to_string(), iri.to_string());
+            }
+        }
+        let prefix_value = if let Some(prefix_value) = self.prefixes.get(prefix) {
+            prefix_value.clone()
+        } else {
+            // We go on to also report the invalid escapes of the local name
+            self.errors.push(FormatError::UndefinedPrefix {
+                span: self.span(node.start_byte(), 0..prefix.len() + 1),
+                prefix: prefix.into(),
+            });
+            String::new()
         };
 
         let mut normalized_local = String::with_capacity(local.len());
         let mut in_escape = false;
-        for c in local.chars() {
+        for (i, c) in local.char_indices() {
             if in_escape {
                 match c {
                     '_' => normalized_local.push(c),
@@ -470,7 +1236,13 @@ impl<W: Write> TurtleFormatter<'_, W> {
                         normalized_local.push('\\');
                         normalized_local.push(c);
                     }
-                    c => bail!("Unexpected escape character \\{c}"),
+                    c => {
+                        let start = prefix.len() + i;
+                        self.errors.push(FormatError::InvalidEscape {
+                            span: self.span(node.start_byte(), start..start + 1 + c.len_utf8()),
+                            message: format!("Unexpected escape character \\{c}"),
+                        });
+                    }
                 }
                 in_escape = false;
             } else if c == '\\' {
@@ -489,80 +1261,231 @@ impl<W: Write> TurtleFormatter<'_, W> {
         Ok(((prefix.to_string(), normalized_local), resolved))
     }
 
+    /// Returns the decoded string value and if it was written using long quotes
     fn extract_string(&mut self, node: Node<'_>) -> Result<(String, bool)> {
         debug_assert_eq!(node.kind(), "string");
 
         let raw = node.utf8_text(self.file)?;
-        if raw.starts_with("\"\"\"") || raw.starts_with("'''") {
-            // We normalize the multi-lines string
-            let mut raw = &raw[3..raw.len() - 3];
-            let mut normalized = String::with_capacity(raw.len());
-            // Hack: double quotes at the end should be escaped
-            let mut number_of_end_double_quotes = 0;
-            loop {
-                if raw.ends_with("\\\"") {
-                    raw = &raw[..raw.len() - 2];
-                    number_of_end_double_quotes += 1;
-                } else if raw.ends_with('"') {
-                    raw = &raw[..raw.len() - 1];
-                    number_of_end_double_quotes += 1;
-                } else {
-                    break;
+        let (raw, is_long) = if raw.starts_with("\"\"\"") || raw.starts_with("'''") {
+            (&raw[3..raw.len() - 3], true)
+        } else {
+            (&raw[1..raw.len() - 1], false)
+        };
+        let start = node.start_byte() + if is_long { 3 } else { 1 };
+        let mut value = String::with_capacity(raw.len());
+        for (range, c) in StringDecoder::new(raw) {
+            match self.decoded_char(start, range, c) {
+                Ok(c) => value.push(c),
+                // We record the error and go on to report the next ones
+                Err(error) => self.errors.push(error),
+            }
+        }
+        let value = match self.options.nfc {
+            NfcPolicy::Ignore => value,
+            NfcPolicy::Report => {
+                if !is_nfc(&value) {
+                    self.warn(node, "The string is not in Unicode NFC".into());
                 }
+                value
+            }
+            NfcPolicy::NormalizeLiterals => value.nfc().collect(),
+        };
+        Ok((value, is_long))
+    }
+
+    fn fmt_string(&mut self, value: &str, is_long_input: bool) -> Result<()> {
+        let (quote, is_long) = match self.options.string_quoting {
+            StringQuoting::Double => ('"', is_long_input),
+            StringQuoting::Adaptive => {
+                let is_long = value.contains('\n');
+                let quote = if needs_quote_escape(value, '"', is_long)
+                    && !needs_quote_escape(value, '\'', is_long)
+                {
+                    '\''
+                } else {
+                    '"'
+                };
+                (quote, is_long)
             }
-            let mut previous_double_quotes = 0;
-            for c in StringDecoder::new(raw) {
-                match c? {
-                    '"' => {
-                        if previous_double_quotes >= 2 {
-                            normalized.push_str("\\\"");
+        };
+        let delimiter = if is_long {
+            quote.to_string().repeat(3)
+        } else {
+            quote.to_string()
+        };
+        write!(self.output, "{delimiter}")?;
+        if is_long {
+            // We normalize the multi-lines string
+            // Quotes at the end and third consecutive quotes must be escaped
+            let end_quotes_start = value.trim_end_matches(quote).len();
+            let mut previous_quotes = 0;
+            for (i, c) in value.char_indices() {
+                match c {
+                    '\\' => {
+                        write!(self.output, "\\\\")?;
+                        previous_quotes = 0;
+                    }
+                    c if c == quote => {
+                        if previous_quotes >= 2 || i >= end_quotes_start {
+                            write!(self.output, "\\{quote}")?;
                         } else {
-                            normalized.push('"');
-                            previous_double_quotes += 1;
+                            write!(self.output, "{quote}")?;
+                            previous_quotes += 1;
                         }
                     }
-                    '\\' => {
-                        normalized.push_str("\\\\");
-                        previous_double_quotes = 0;
+                    '\n' | '\t' => {
+                        write!(self.output, "{c}")?;
+                        previous_quotes = 0;
                     }
                     c => {
-                        normalized.push(c);
-                        previous_double_quotes = 0;
+                        self.fmt_string_char(c)?;
+                        previous_quotes = 0;
                     }
                 }
             }
-            for _ in 0..number_of_end_double_quotes {
-                normalized.push_str("\\\"");
-            }
-
-            Ok((normalized, true))
         } else {
             // We normalize the one-line string
-            let raw = &raw[1..raw.len() - 1];
-            let mut normalized = String::with_capacity(raw.len());
-            for c in StringDecoder::new(raw) {
-                match c? {
-                    '"' => {
-                        normalized.push_str("\\\"");
-                    }
-                    '\\' => {
-                        normalized.push_str("\\\\");
-                    }
-                    '\r' => {
-                        normalized.push_str("\\r");
-                    }
-                    '\n' => {
-                        normalized.push_str("\\n");
-                    }
-                    '\t' => {
-                        normalized.push_str("\\t");
+            for c in value.chars() {
+                match c {
+                    '\\' => write!(self.output, "\\\\"),
+                    '\r' => write!(self.output, "\\r"),
+                    '\n' => write!(self.output, "\\n"),
+                    '\t' => write!(self.output, "\\t"),
+                    c if c == quote => write!(self.output, "\\{quote}"),
+                    c => {
+                        self.fmt_string_char(c)?;
+                        Ok(())
                     }
-                    c => normalized.push(c),
+                }?;
+            }
+        }
+        write!(self.output, "{delimiter}")?;
+        Ok(())
+    }
+
+    fn fmt_string_char(&mut self, c: char) -> Result<()> {
+        if self.options.escaping.must_escape(c) {
+            match c {
+                '\x08' => write!(self.output, "\\b")?,
+                '\x0C' => write!(self.output, "\\f")?,
+                '\r' => write!(self.output, "\\r")?,
+                c => self.fmt_uchar(c)?,
+            }
+        } else {
+            write!(self.output, "{c}")?;
+        }
+        Ok(())
+    }
+
+    fn warn(&mut self, node: Node<'_>, message: String) {
+        self.warnings.push(Warning {
+            line: node.start_position().row + 1,
+            span: node.into(),
+            message,
+        });
+    }
+
+    /// Formats comments on consecutive lines, wrapping them to the maximal line width if set
+    fn fmt_comment_block(&mut self, nodes: &[Node<'_>]) -> Result<()> {
+        let comments = nodes
+            .iter()
+            .map(|node| node.utf8_text(self.file))
+            .collect::<Result<Vec<_>, _>>()?;
+        let Some(max_width) = self.options.max_width else {
+            for (i, comment) in comments.into_iter().enumerate() {
+                if i > 0 {
+                    writeln!(self.output)?;
+                }
+                self.fmt_comment(comment)?;
+            }
+            return Ok(());
+        };
+
+        // We build the paragraphs: indentation of the first and the next lines and words
+        // The directives are kept on their own lines to stay recognized by the next runs
+        let mut paragraphs: Vec<(Option<&str>, usize, usize, Vec<&str>)> = Vec::new();
+        for (node, comment) in nodes.iter().zip(comments) {
+            let text = comment[1..].strip_prefix(' ').unwrap_or(&comment[1..]);
+            let indent = text.len() - text.trim_start().len();
+            let text = text.trim();
+            if self.is_verbatim_comment(comment)
+                || text.is_empty()
+                || self.comment_directive(*node).is_some()
+            {
+                paragraphs.push((Some(comment), 0, 0, Vec::new()));
+            } else if let Some(marker_len) = list_item_marker_len(text) {
+                paragraphs.push((
+                    None,
+                    indent,
+                    indent + marker_len,
+                    text.split_whitespace().collect(),
+                ));
+            } else if let Some((None, _, _, words)) = paragraphs
+                .last_mut()
+                .filter(|_| self.options.join_comment_lines)
+            {
+                words.extend(text.split_whitespace());
+            } else {
+                paragraphs.push((None, indent, indent, text.split_whitespace().collect()));
+            }
+        }
+
+        for (i, (verbatim, first_indent, next_indent, words)) in paragraphs.into_iter().enumerate()
+        {
+            if i > 0 {
+                writeln!(self.output)?;
+            }
+            if let Some(comment) = verbatim {
+                self.fmt_comment(comment)?;
+                continue;
+            }
+            write!(self.output, "#{}", " ".repeat(first_indent))?;
+            let mut width = 1 + first_indent;
+            let mut is_line_start = true;
+            for word in words {
+                let word_width = word.chars().count();
+                if !is_line_start && width + 1 + word_width > max_width {
+                    writeln!(self.output)?;
+                    write!(self.output, "#{}", " ".repeat(next_indent))?;
+                    width = 1 + next_indent;
                 }
+                write!(self.output, " {word}")?;
+                width += 1 + word_width;
+                is_line_start = false;
             }
+        }
+        Ok(())
+    }
 
-            Ok((normalized, false))
+    /// Returns the formatter directive (`off`, `on` or `skip`) of a `# turtlefmt: ...` comment
+    fn comment_directive(&self, node: Node<'_>) -> Option<&'a str> {
+        let directive = node
+            .utf8_text(self.file)
+            .ok()?
+            .strip_prefix('#')?
+            .trim()
+            .strip_prefix("turtlefmt:")?
+            .trim();
+        matches!(directive, "off" | "on" | "skip").then_some(directive)
+    }
+
+    /// Checks if the comment must be kept as is
+    fn is_verbatim_comment(&self, comment: &str) -> bool {
+        self.options.verbatim_comments || comment.starts_with("#!")
+    }
+
+    fn fmt_comment(&mut self, comment: &str) -> Result<()> {
+        if self.is_verbatim_comment(comment) {
+            write!(self.output, "{}", comment.trim_end())?;
+        } else {
+            let text = comment[1..].trim();
+            if text.is_empty() {
+                write!(self.output, "#")?;
+            } else {
+                write!(self.output, "# {text}")?;
+            }
         }
+        Ok(())
     }
 
     fn fmt_comments<'b>(
@@ -572,13 +1495,25 @@ impl<W: Write> TurtleFormatter<'_, W> {
     ) -> Result<()> {
         let comments = nodes
             .into_iter()
-            .map(|node| Ok(node.utf8_text(self.file)?[1..].trim()))
-            .collect::<Result<Vec<_>>>()?;
+            .map(|node| node.utf8_text(self.file))
+            .collect::<Result<Vec<_>, _>>()?;
         if !comments.is_empty() {
             if inline {
+                self.trailing_comments.push(self.output.len());
                 write!(self.output, " ")?;
             }
-            write!(self.output, "# {}", comments.join(" "))?;
+            if comments.iter().any(|c| self.is_verbatim_comment(c)) {
+                // We can't merge the comments without modifying them
+                for (i, comment) in comments.into_iter().enumerate() {
+                    if i > 0 {
+                        write!(self.output, " ")?;
+                    }
+                    self.fmt_comment(comment)?;
+                }
+            } else {
+                let comments = comments.iter().map(|c| c[1..].trim()).collect::<Vec<_>>();
+                write!(self.output, "# {}", comments.join(" "))?;
+            }
         }
         Ok(())
     }
@@ -588,7 +1523,7 @@ impl<W: Write> TurtleFormatter<'_, W> {
         node.children(&mut walk)
             .filter_map(|child| {
                 if child.is_error() || child.is_missing() {
-                    Some(Err(Self::fmt_err(child)))
+                    Some(Err(Self::syntax_error(child)))
                 } else if child.is_named() {
                     Some(Ok(child))
                 } else {
@@ -598,24 +1533,41 @@ impl<W: Write> TurtleFormatter<'_, W> {
             .collect()
     }
 
-    fn fmt_err(node: Node<'_>) -> Error {
-        let start = node.start_position();
-        let end = node.end_position();
-        if start.row == end.row {
-            anyhow!(
-                "Error on line {} between bytes {} and {}: {}",
-                start.row + 1,
-                start.column + 1,
-                end.column + 1,
-                node.to_sexp()
-            )
-        } else {
-            anyhow!(
-                "Error between lines {} and {}: {}",
-                start.row + 1,
-                end.row + 1,
-                node.to_sexp()
-            )
+    /// Adds all the error and missing nodes inside of the node
+    fn collect_errors<'b>(node: Node<'b>, errors: &mut Vec<Node<'b>>) {
+        if node.is_error() || node.is_missing() {
+            errors.push(node);
+            return;
+        }
+        let mut walk = node.walk();
+        for child in node.children(&mut walk) {
+            if child.has_error() {
+                Self::collect_errors(child, errors);
+            }
+        }
+    }
+
+    /// Returns the first error or missing node inside of the node
+    fn first_error(node: Node<'_>) -> Node<'_> {
+        if node.is_error() || node.is_missing() {
+            return node;
+        }
+        let mut walk = node.walk();
+        let child = node.children(&mut walk).find(|child| child.has_error());
+        child.map_or(node, Self::first_error)
+    }
+
+    fn syntax_error(node: Node<'_>) -> FormatError {
+        FormatError::Syntax {
+            span: node.into(),
+            // The named nodes inserted by the parser are arbitrary guesses (a boolean for a missing object...)
+            missing: (node.is_missing() && !node.is_named()).then(|| node.kind().to_string()),
+        }
+    }
+
+    fn unexpected(message: &str, node: Node<'_>) -> FormatError {
+        FormatError::Internal {
+            message: format!("{message}: {}", node.to_sexp()),
         }
     }
 }
@@ -632,11 +1584,13 @@ impl<'a> StringDecoder<'a> {
 }
 
 impl Iterator for StringDecoder<'_> {
-    type Item = Result<char>;
+    /// The decoded character or the byte range of the invalid escape sequence with an error message
+    type Item = (Range<usize>, Result<char, String>);
 
-    fn next(&mut self) -> Option<Result<char>> {
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.i;
         let c = self.input[self.i..].chars().next()?;
-        Some(if c == '\\' {
+        let c = if c == '\\' {
             match self.input[self.i + 1..].chars().next().unwrap() {
                 'u' => {
                     self.i += 6;
@@ -654,11 +1608,12 @@ impl Iterator for StringDecoder<'_> {
         } else {
             self.i += c.len_utf8();
             Ok(c)
-        })
+        };
+        Some((start..self.i, c))
     }
 }
 
-fn decode_echar(c: char) -> Result<char> {
+fn decode_echar(c: char) -> Result<char, String> {
     match c {
         't' => Ok('\t'),
         'b' => Ok('\x08'),
@@ -668,16 +1623,72 @@ fn decode_echar(c: char) -> Result<char> {
         '"' => Ok('"'),
         '\'' => Ok('\''),
         '\\' => Ok('\\'),
-        _ => bail!("The escaped character '\\{c}' is not valid"),
+        _ => Err(format!("The escaped character '\\{c}' is not valid")),
     }
 }
 
-fn decode_uchar(input: &str) -> Result<char> {
+fn decode_uchar(input: &str) -> Result<char, String> {
     char::from_u32(u32::from_str_radix(&input[2..], 16).unwrap()).ok_or_else(|| {
-        anyhow!("The escaped unicode character '{input}' is not encoding a valid unicode character")
+        format!("The escaped unicode character '{input}' is not encoding a valid unicode character")
     })
 }
 
+/// Checks if the character is a control, format or bidirectional formatting character or a line or paragraph separator
+fn is_invisible_char(c: char) -> bool {
+    c.is_control()
+        || matches!(
+            c,
+            '\u{00AD}'
+                | '\u{0600}'..='\u{0605}'
+                | '\u{061C}'
+                | '\u{06DD}'
+                | '\u{070F}'
+                | '\u{0890}'..='\u{0891}'
+                | '\u{08E2}'
+                | '\u{180E}'
+                | '\u{200B}'..='\u{200F}'
+                | '\u{2028}'..='\u{202E}'
+                | '\u{2060}'..='\u{2064}'
+                | '\u{2066}'..='\u{206F}'
+                | '\u{FEFF}'
+                | '\u{FFF9}'..='\u{FFFB}'
+                | '\u{110BD}'
+                | '\u{110CD}'
+                | '\u{13430}'..='\u{1343F}'
+                | '\u{1BCA0}'..='\u{1BCA3}'
+                | '\u{1D173}'..='\u{1D17A}'
+                | '\u{E0001}'
+                | '\u{E0020}'..='\u{E007F}'
+        )
+}
+
+/// Returns the length of the Markdown list item marker ("- ", "* ", "1. "...) if the text starts with one
+fn list_item_marker_len(text: &str) -> Option<usize> {
+    let digits = text.bytes().take_while(u8::is_ascii_digit).count();
+    let marker = if digits > 0 {
+        text[digits..]
+            .bytes()
+            .next()
+            .filter(|c| matches!(c, b'.' | b')'))?;
+        digits + 1
+    } else {
+        text.bytes()
+            .next()
+            .filter(|c| matches!(c, b'-' | b'*' | b'+'))?;
+        1
+    };
+    text[marker..].starts_with(' ').then_some(marker + 1)
+}
+
+/// Checks if the string contains quotes that would need to be escaped if written with the given quote
+fn needs_quote_escape(value: &str, quote: char, is_long: bool) -> bool {
+    if is_long {
+        value.ends_with(quote) || value.contains(&quote.to_string().repeat(3))
+    } else {
+        value.contains(quote)
+    }
+}
+
 fn is_turtle_integer(value: &str) -> bool {
     // [19] 	INTEGER 	::= 	[+-]? [0-9]+
     let mut value = value.as_bytes();
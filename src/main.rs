@@ -15,82 +15,1696 @@
 */
 
 use anyhow::{bail, Context, Result};
-use clap::Parser;
-use diffy::{create_patch, PatchFormatter};
+use clap::{Parser, ValueEnum};
+use diffy::{create_patch, Hunk, Line, PatchFormatter};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
 use std::ffi::OsStr;
+use std::fmt;
 use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::iter;
+use std::num::NonZeroUsize;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 use std::process::ExitCode;
-use turtlefmt::{format_turtle, FormatOptions};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use turtlefmt::{
+    extract_prefixes, CharacterEscaping, FormatError, FormatOptions, Formatter, NfcPolicy,
+    Position, Span, StringQuoting, Warning,
+};
 
 /// Apply a consistent formatting to a Turtle file
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
 struct Args {
-    /// File(s) or directory to format.
+    /// File(s) or directory to format. Use `-` to read from stdin.
     #[arg()]
     src: Vec<PathBuf>,
+    /// Read the Turtle file from stdin and write the formatted file to stdout
+    #[arg(long)]
+    stdin: bool,
+    /// Name of the file read from stdin, used in messages
+    #[arg(long, value_name = "PATH")]
+    stdin_filepath: Option<PathBuf>,
     /// Do not edit the file but only check if it already applies this tools format.
     #[arg(long)]
     check: bool,
-    /// Number of spaces per level of indentation
-    #[arg(long, default_value = "4")]
-    indentation: usize,
+    /// Number of spaces per level of indentation [default: 4]
+    #[arg(long)]
+    indentation: Option<usize>,
+    /// Add the missing declarations of well-known prefixes like rdf, xsd or owl
+    #[arg(
+        long,
+        num_args = 0,
+        default_missing_value = "true",
+        overrides_with = "no_fix_prefixes"
+    )]
+    fix_prefixes: Option<bool>,
+    /// Do not add the missing prefix declarations, even if enabled in a configuration file
+    #[arg(
+        long,
+        num_args = 0,
+        default_missing_value = "false",
+        overrides_with = "fix_prefixes"
+    )]
+    no_fix_prefixes: Option<bool>,
+    /// Turtle file(s) declaring prefixes that the formatted files may use without declaring them
+    #[arg(long, value_name = "FILE")]
+    prefixes: Vec<PathBuf>,
+    /// Fail on language tags that are not well-formed according to BCP 47
+    #[arg(
+        long,
+        num_args = 0,
+        default_missing_value = "true",
+        overrides_with = "no_validate_language_tags"
+    )]
+    validate_language_tags: Option<bool>,
+    /// Do not validate the language tags, even if enabled in a configuration file
+    #[arg(
+        long,
+        num_args = 0,
+        default_missing_value = "false",
+        overrides_with = "validate_language_tags"
+    )]
+    no_validate_language_tags: Option<bool>,
+    /// Rewrite boolean and numeric literals to their XSD canonical lexical form.
+    /// This keeps the literal values but changes their lexical forms.
+    #[arg(
+        long,
+        num_args = 0,
+        default_missing_value = "true",
+        overrides_with = "no_canonical_literals"
+    )]
+    canonical_literals: Option<bool>,
+    /// Keep the lexical form of boolean and numeric literals, even if enabled in a configuration file
+    #[arg(
+        long,
+        num_args = 0,
+        default_missing_value = "false",
+        overrides_with = "canonical_literals"
+    )]
+    no_canonical_literals: Option<bool>,
+    /// How to quote strings
+    #[arg(long, value_enum)]
+    string_quoting: Option<QuotingArg>,
+    /// Which characters to keep escaped in strings and IRIs
+    #[arg(long, value_enum)]
+    escaping: Option<EscapingArg>,
+    /// What to do with strings, IRIs and prefixed names that are not in Unicode NFC
+    #[arg(long, value_enum)]
+    nfc: Option<NfcArg>,
+    /// Keep the comments text as is. Comments starting with `#!` are always kept as is.
+    #[arg(
+        long,
+        num_args = 0,
+        default_missing_value = "true",
+        overrides_with = "no_verbatim_comments"
+    )]
+    verbatim_comments: Option<bool>,
+    /// Normalize the comments text, even if enabled in a configuration file
+    #[arg(
+        long,
+        num_args = 0,
+        default_missing_value = "false",
+        overrides_with = "verbatim_comments"
+    )]
+    no_verbatim_comments: Option<bool>,
+    /// Maximal line width: longer comments between statements are wrapped
+    #[arg(long)]
+    max_width: Option<usize>,
+    /// Join consecutive comment lines into paragraphs when wrapping them to the maximal width
+    #[arg(
+        long,
+        num_args = 0,
+        default_missing_value = "true",
+        overrides_with = "no_join_comment_lines"
+    )]
+    join_comment_lines: Option<bool>,
+    /// Do not join consecutive comment lines, even if enabled in a configuration file
+    #[arg(
+        long,
+        num_args = 0,
+        default_missing_value = "false",
+        overrides_with = "join_comment_lines"
+    )]
+    no_join_comment_lines: Option<bool>,
+    /// Align the comments trailing consecutive lines to the same column
+    #[arg(
+        long,
+        num_args = 0,
+        default_missing_value = "true",
+        overrides_with = "no_align_comments"
+    )]
+    align_comments: Option<bool>,
+    /// Do not align the trailing comments, even if enabled in a configuration file
+    #[arg(
+        long,
+        num_args = 0,
+        default_missing_value = "false",
+        overrides_with = "align_comments"
+    )]
+    no_align_comments: Option<bool>,
+    /// Format the valid statements and keep the invalid ones as is instead of failing
+    #[arg(
+        long,
+        num_args = 0,
+        default_missing_value = "true",
+        overrides_with = "no_keep_invalid_statements"
+    )]
+    keep_invalid_statements: Option<bool>,
+    /// Fail on invalid statements, even if enabled in a configuration file
+    #[arg(
+        long,
+        num_args = 0,
+        default_missing_value = "false",
+        overrides_with = "keep_invalid_statements"
+    )]
+    no_keep_invalid_statements: Option<bool>,
+    /// Maximal number of errors to report per file [default: 20]
+    #[arg(long)]
+    max_errors: Option<NonZeroUsize>,
+    /// How to report the result of each file
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output_format: OutputFormat,
+    /// Do not edit the files but only print the names of the ones whose format is not correct
+    #[arg(long)]
+    list_different: bool,
+    /// Only print the errors
+    #[arg(long, short, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Also print the files that are already formatted
+    #[arg(long, short)]
+    verbose: bool,
+    /// When to color the patches
+    #[arg(long, value_enum, default_value_t = ColorArg::Auto)]
+    color: ColorArg,
+    /// Go on with the next files after an invalid, unreadable or missing file
+    #[arg(long)]
+    keep_going: bool,
+    /// Extension of the files to format in the directories [default: ttl]
+    #[arg(long = "extension", value_name = "EXT")]
+    extensions: Vec<String>,
+    /// Only format the files of the directories whose path matches this glob pattern
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+    /// Do not format the files and directories whose path matches this glob pattern
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+    /// Follow the symbolic links when looking for files in the directories
+    #[arg(
+        long,
+        num_args = 0,
+        default_missing_value = "true",
+        overrides_with = "no_follow_symlinks"
+    )]
+    follow_symlinks: Option<bool>,
+    /// Do not follow the symbolic links, even if enabled in a configuration file
+    #[arg(
+        long,
+        num_args = 0,
+        default_missing_value = "false",
+        overrides_with = "follow_symlinks"
+    )]
+    no_follow_symlinks: Option<bool>,
+    /// Number of files to format in parallel [default: the number of CPUs]
+    #[arg(long, short)]
+    jobs: Option<NonZeroUsize>,
+}
+
+impl Args {
+    /// --list-different checks the files like --check
+    fn check(&self) -> bool {
+        self.check || self.list_different
+    }
+}
+
+/// Exit code if a file is invalid or if an other error happened
+const EXIT_ERROR: u8 = 1;
+/// Exit code if the format of a file is not correct with --check
+const EXIT_NOT_FORMATTED: u8 = 65;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+enum ColorArg {
+    /// Color if the output is a terminal and NO_COLOR is not set
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorArg {
+    fn is_enabled(self) -> bool {
+        match self {
+            Self::Auto => io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none(),
+            Self::Always => true,
+            Self::Never => false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+enum OutputFormat {
+    /// Human readable messages and colored diffs
+    Text,
+    /// One JSON object per file, on its own line
+    Json,
+    /// A SARIF 2.1.0 log with a result per issue, for code scanning tools
+    Sarif,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+enum QuotingArg {
+    /// Always use `"` and keep `"""` only if the input string used it
+    Double,
+    /// Use `"""` only for strings with line breaks and `'` if it avoids escaping `"`
+    Adaptive,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+enum EscapingArg {
+    /// Unescape all characters that are allowed to be written as is
+    Minimal,
+    /// Keep control, format and bidirectional characters escaped
+    Invisible,
+    /// Escape all non-ASCII characters
+    Ascii,
+}
+
+impl From<EscapingArg> for CharacterEscaping {
+    fn from(value: EscapingArg) -> Self {
+        match value {
+            EscapingArg::Minimal => Self::Minimal,
+            EscapingArg::Invisible => Self::Invisible,
+            EscapingArg::Ascii => Self::Ascii,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+enum NfcArg {
+    /// Do not check the normalization form
+    Ignore,
+    /// Report the strings, IRIs and prefixed names not in NFC
+    Report,
+    /// Normalize the strings to NFC and report the IRIs and prefixed names not in NFC
+    NormalizeLiterals,
+}
+
+impl From<NfcArg> for NfcPolicy {
+    fn from(value: NfcArg) -> Self {
+        match value {
+            NfcArg::Ignore => Self::Ignore,
+            NfcArg::Report => Self::Report,
+            NfcArg::NormalizeLiterals => Self::NormalizeLiterals,
+        }
+    }
+}
+
+impl From<QuotingArg> for StringQuoting {
+    fn from(value: QuotingArg) -> Self {
+        match value {
+            QuotingArg::Double => Self::Double,
+            QuotingArg::Adaptive => Self::Adaptive,
+        }
+    }
 }
 
 fn main() -> Result<ExitCode> {
     let args = Args::parse();
-    let options = FormatOptions {
-        indentation: args.indentation,
+    let is_stdin = args.stdin || args.src.iter().any(|source| source == Path::new("-"));
+    let check = args.check();
+    if is_stdin && args.src.iter().any(|source| source != Path::new("-")) {
+        bail!("It is not possible to format both stdin and files");
+    }
+    if is_stdin && !check && args.output_format != OutputFormat::Text {
+        bail!("The formatted file is written to stdout, --output-format requires --check or --list-different when reading from stdin");
+    }
+    if args.stdin_filepath.is_some() && !is_stdin {
+        bail!("--stdin-filepath requires reading from stdin");
+    }
+
+    let selection = FileSelection {
+        include: build_glob_set(&args.include)?,
+        exclude: build_glob_set(&args.exclude)?,
     };
-    let mut exit_code = ExitCode::SUCCESS;
-
-    let mut files = Vec::new();
-    for source in args.src {
-        if source.is_file() {
-            files.push(source);
-        } else if source.is_dir() {
-            add_files_with_suffix(&source, OsStr::new("ttl"), &mut files)?;
+    let mut project = Project::new(&args);
+    let files = selection.targets(is_stdin, &mut project)?;
+
+    if files
+        .iter()
+        .any(|(_, options)| options.canonicalize_literals)
+    {
+        eprintln!("Warning: --canonical-literals rewrites the lexical form of boolean and numeric literals. Their values are kept but the RDF terms are changed.");
+    }
+    if files
+        .iter()
+        .any(|(_, options)| options.nfc == NfcPolicy::NormalizeLiterals)
+    {
+        eprintln!("Warning: --nfc normalize-literals rewrites the strings that are not in Unicode NFC. This changes the literals.");
+    }
+
+    let is_text = args.output_format == OutputFormat::Text;
+    let mut summary = Summary::default();
+    let mut sarif_results = Vec::new();
+    let jobs = args
+        .jobs
+        .or_else(|| thread::available_parallelism().ok())
+        .map_or(1, NonZeroUsize::get);
+    let next_file = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    thread::scope(|scope| -> Result<()> {
+        // Each worker formats the next file not taken yet with its own parser
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..jobs.min(files.len()) {
+            let sender = sender.clone();
+            let (files, next_file, stop) = (&files, &next_file, &stop);
+            scope.spawn(move || {
+                let mut formatter = Formatter::new();
+                while !stop.load(Ordering::Relaxed) {
+                    let index = next_file.fetch_add(1, Ordering::Relaxed);
+                    let Some((file, options)) = files.get(index) else {
+                        break;
+                    };
+                    let outcome = read_file(file, is_stdin).map(|original| {
+                        let result = formatter.format_with_warnings(&original, options);
+                        (original, result)
+                    });
+                    if sender.send((index, outcome)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        // The results are reported in the order of the files whatever the worker finishing first
+        let mut pending = BTreeMap::new();
+        let mut next_index = 0;
+        let outcomes = iter::from_fn(|| loop {
+            if let Some(outcome) = pending.remove(&next_index) {
+                next_index += 1;
+                return Some(outcome);
+            }
+            let (index, outcome) = receiver.recv().ok()?;
+            pending.insert(index, outcome);
+        });
+        for ((file, _), outcome) in files.iter().zip(outcomes) {
+            let (original, result) = match outcome {
+                Ok(outcome) => outcome,
+                Err(error) if args.keep_going => {
+                    summary.failed += 1;
+                    let mut report = FileReport::new(file, FileStatus::Error);
+                    report
+                        .diagnostics
+                        .push(JsonDiagnostic::from_io_error(&error));
+                    match args.output_format {
+                        OutputFormat::Text => eprintln!("Error: {error:#}"),
+                        OutputFormat::Json => println!("{}", serde_json::to_string(&report)?),
+                        OutputFormat::Sarif => sarif_results.extend(report.sarif_results()),
+                    }
+                    continue;
+                }
+                Err(error) => return Err(error),
+            };
+            let (formatted, warnings) = match result {
+                Ok(result) => result,
+                Err(errors) => {
+                    summary.failed += 1;
+                    let mut report = FileReport::new(file, FileStatus::Error);
+                    report.diagnostics = errors
+                        .0
+                        .iter()
+                        .map(|e| JsonDiagnostic::from_error(&original, e))
+                        .collect();
+                    match args.output_format {
+                        OutputFormat::Text => {
+                            for error in errors.0 {
+                                eprintln!("{}", render_error(file, &original, &error));
+                            }
+                        }
+                        OutputFormat::Json => println!("{}", serde_json::to_string(&report)?),
+                        OutputFormat::Sarif => sarif_results.extend(report.sarif_results()),
+                    }
+                    if args.keep_going {
+                        continue;
+                    }
+                    break;
+                }
+            };
+            let status = FileStatus::new(&original, &formatted, check);
+            let patch = create_patch(&original, &formatted);
+            let mut report = FileReport::new(file, status);
+            report.diagnostics = warnings
+                .iter()
+                .map(|warning| JsonDiagnostic::from_warning(&original, warning))
+                .collect();
+            if status != FileStatus::Unchanged {
+                report.hunks = Some(patch.hunks().iter().map(JsonHunk::from).collect());
+            }
+            match args.output_format {
+                OutputFormat::Text => {
+                    if !args.quiet {
+                        for warning in warnings {
+                            eprintln!("Warning in {}: {warning}", file.display());
+                        }
+                    }
+                    match status {
+                        FileStatus::WouldChange if args.list_different => {
+                            println!("{}", file.display())
+                        }
+                        FileStatus::WouldChange if !args.quiet => {
+                            eprintln!("The format of {} is not correct", file.display());
+                            let mut formatter = PatchFormatter::new();
+                            if args.color.is_enabled() {
+                                formatter = formatter.with_color();
+                            }
+                            println!("{}", formatter.fmt_patch(&patch));
+                        }
+                        FileStatus::Unchanged if args.verbose => {
+                            eprintln!("{} is already formatted", file.display())
+                        }
+                        FileStatus::Formatted if args.verbose => {
+                            eprintln!("Formatted {}", file.display())
+                        }
+                        _ => (),
+                    }
+                }
+                OutputFormat::Json => println!("{}", serde_json::to_string(&report)?),
+                OutputFormat::Sarif => sarif_results.extend(report.sarif_results()),
+            }
+            summary.add(status);
+            if is_stdin {
+                if !check {
+                    io::stdout().write_all(formatted.as_bytes())?;
+                }
+            } else if status == FileStatus::Formatted {
+                if let Err(error) = fs::write(file, &formatted) {
+                    let error = anyhow::Error::from(error)
+                        .context(format!("Error while writing {}", file.display()));
+                    if !args.keep_going {
+                        return Err(error);
+                    }
+                    summary.failed += 1;
+                    eprintln!("Error: {error:#}");
+                }
+            }
+        }
+        // Prevents the workers from formatting files that will not be reported
+        stop.store(true, Ordering::Relaxed);
+        Ok(())
+    })?;
+    if args.output_format == OutputFormat::Sarif {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&sarif_log(sarif_results))?
+        );
+    }
+    if is_text && !args.quiet && !is_stdin {
+        eprintln!("{summary}");
+    }
+    Ok(ExitCode::from(summary.exit_code()))
+}
+
+/// Counts of the files per result
+#[derive(Default)]
+struct Summary {
+    formatted: usize,
+    unchanged: usize,
+    not_formatted: usize,
+    /// Invalid or unreadable files
+    failed: usize,
+}
+
+impl Summary {
+    fn add(&mut self, status: FileStatus) {
+        match status {
+            FileStatus::Unchanged => self.unchanged += 1,
+            FileStatus::WouldChange => self.not_formatted += 1,
+            FileStatus::Formatted => self.formatted += 1,
+            FileStatus::Error => self.failed += 1,
+        }
+    }
+
+    /// The errors take precedence over the files not formatted
+    fn exit_code(&self) -> u8 {
+        if self.failed > 0 {
+            EXIT_ERROR
+        } else if self.not_formatted > 0 {
+            EXIT_NOT_FORMATTED
         } else {
-            bail!(
-                "The target to format {} does not seem to exist",
-                source.display()
+            0
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.formatted + self.unchanged + self.not_formatted + self.failed;
+        write!(f, "{total} file{}: ", if total == 1 { "" } else { "s" })?;
+        let mut parts = Vec::new();
+        if self.formatted > 0 {
+            parts.push(format!("{} formatted", self.formatted));
+        }
+        if self.not_formatted > 0 {
+            parts.push(format!("{} not formatted", self.not_formatted));
+        }
+        if self.unchanged > 0 {
+            parts.push(format!("{} already formatted", self.unchanged));
+        }
+        if self.failed > 0 {
+            parts.push(format!("{} failed", self.failed));
+        }
+        if parts.is_empty() {
+            parts.push("nothing to do".into());
+        }
+        f.write_str(&parts.join(", "))
+    }
+}
+
+/// The result of a file in the JSON output
+#[derive(Serialize)]
+struct FileReport<'a> {
+    file: &'a Path,
+    status: FileStatus,
+    diagnostics: Vec<JsonDiagnostic>,
+    /// The diff between the original file and the formatted one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hunks: Option<Vec<JsonHunk>>,
+}
+
+impl<'a> FileReport<'a> {
+    fn new(file: &'a Path, status: FileStatus) -> Self {
+        Self {
+            file,
+            status,
+            diagnostics: Vec::new(),
+            hunks: None,
+        }
+    }
+
+    /// The SARIF results of the diagnostics and, with --check, of the hunks to reformat
+    fn sarif_results(&self) -> Vec<serde_json::Value> {
+        let mut uri = self.file.to_string_lossy().replace('\\', "/");
+        if self.file.is_absolute() {
+            uri = format!(
+                "file://{}{uri}",
+                if uri.starts_with('/') { "" } else { "/" }
             );
         }
+        let mut results = Vec::new();
+        for diagnostic in &self.diagnostics {
+            let region = if let Some(span) = &diagnostic.span {
+                json!({
+                    "startLine": span.start.line,
+                    "startColumn": span.start.column,
+                    "endLine": span.end.line,
+                    "endColumn": span.end.column,
+                })
+            } else {
+                json!({ "startLine": diagnostic.line.unwrap_or(1) })
+            };
+            results.push(sarif_result(
+                diagnostic.code,
+                diagnostic.severity.as_str(),
+                &diagnostic.message,
+                &uri,
+                region,
+            ));
+        }
+        if self.status == FileStatus::WouldChange {
+            for hunk in self.hunks.iter().flatten() {
+                let message = format!(
+                    "The format of these lines is not correct:\n{}",
+                    hunk.lines.join("\n")
+                );
+                let region = json!({
+                    "startLine": hunk.old_start.max(1),
+                    "endLine": (hunk.old_start + hunk.old_lines).max(2) - 1,
+                });
+                results.push(sarif_result("format", "error", &message, &uri, region));
+            }
+        }
+        results
     }
+}
 
-    for file in files {
-        let original = fs::read_to_string(&file)
-            .with_context(|| format!("Error while reading {}", file.display()))?;
-        let formatted = format_turtle(&original, &options)?;
+/// The rules of the SARIF log with their descriptions
+const SARIF_RULES: &[(&str, &str)] = &[
+    ("format", "The file is not formatted"),
+    ("syntax-error", "The file is not valid Turtle"),
+    (
+        "undefined-prefix",
+        "A prefixed name uses a prefix that is not declared",
+    ),
+    ("invalid-escape", "An escape sequence is not valid"),
+    (
+        "invalid-iri-character",
+        "An IRI contains a character not allowed in IRIs",
+    ),
+    ("invalid-language-tag", "A language tag is not well-formed"),
+    ("internal-error", "An unexpected error in turtlefmt"),
+    ("io-error", "The file could not be read"),
+    ("warning", "An issue that does not prevent formatting"),
+];
+
+fn sarif_result(
+    rule: &str,
+    level: &str,
+    message: &str,
+    uri: &str,
+    region: serde_json::Value,
+) -> serde_json::Value {
+    json!({
+        "ruleId": rule,
+        "level": level,
+        "message": { "text": message },
+        "locations": [{
+            "physicalLocation": {
+                "artifactLocation": { "uri": uri },
+                "region": region,
+            }
+        }],
+    })
+}
+
+/// Builds a SARIF 2.1.0 log of a run
+fn sarif_log(results: Vec<serde_json::Value>) -> serde_json::Value {
+    let rules = SARIF_RULES
+        .iter()
+        .map(|(id, description)| json!({ "id": id, "shortDescription": { "text": description } }))
+        .collect::<Vec<_>>();
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "turtlefmt",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": env!("CARGO_PKG_REPOSITORY"),
+                    "rules": rules,
+                }
+            },
+            "columnKind": "unicodeCodePoints",
+            "results": results,
+        }],
+    })
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum FileStatus {
+    /// The file has been rewritten
+    Formatted,
+    /// The file is already formatted
+    Unchanged,
+    /// The file is not formatted (with --check)
+    WouldChange,
+    /// The file could not be formatted
+    Error,
+}
+
+impl FileStatus {
+    fn new(original: &str, formatted: &str, check: bool) -> Self {
         if original == formatted {
-            // Nothing to do
-            continue;
+            Self::Unchanged
+        } else if check {
+            Self::WouldChange
+        } else {
+            Self::Formatted
         }
-        if args.check {
-            let patch = create_patch(&original, &formatted);
-            eprintln!("The format of {} is not correct", file.display());
-            println!("{}", PatchFormatter::new().with_color().fmt_patch(&patch));
-            exit_code = ExitCode::from(65);
+    }
+}
+
+#[derive(Serialize)]
+struct JsonDiagnostic {
+    /// The kind of the diagnostic like `syntax-error`
+    code: &'static str,
+    severity: Severity,
+    message: String,
+    /// Line of the diagnostic, starting from 1
+    line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    span: Option<JsonSpan>,
+}
+
+impl JsonDiagnostic {
+    fn from_error(source: &str, error: &FormatError) -> Self {
+        let span = error.span().map(|span| JsonSpan::new(source, span));
+        let code = match error {
+            FormatError::Syntax { .. } => "syntax-error",
+            FormatError::UndefinedPrefix { .. } => "undefined-prefix",
+            FormatError::InvalidEscape { .. } => "invalid-escape",
+            FormatError::InvalidIriCharacter { .. } => "invalid-iri-character",
+            FormatError::InvalidLanguageTag { .. } => "invalid-language-tag",
+            FormatError::InvalidRange { .. } | FormatError::Internal { .. } => "internal-error",
+        };
+        Self {
+            code,
+            severity: Severity::Error,
+            message: error.message(),
+            line: span.as_ref().map(|span| span.start.line),
+            span,
+        }
+    }
+
+    fn from_io_error(error: &anyhow::Error) -> Self {
+        Self {
+            code: "io-error",
+            severity: Severity::Error,
+            message: format!("{error:#}"),
+            line: None,
+            span: None,
+        }
+    }
+
+    fn from_warning(source: &str, warning: &Warning) -> Self {
+        Self {
+            code: "warning",
+            severity: Severity::Warning,
+            message: warning.message.clone(),
+            line: Some(warning.line),
+            span: Some(JsonSpan::new(source, &warning.span)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonSpan {
+    start: JsonPosition,
+    end: JsonPosition,
+    /// Byte range in the file
+    bytes: Range<usize>,
+}
+
+impl JsonSpan {
+    fn new(source: &str, span: &Span) -> Self {
+        Self {
+            start: JsonPosition::new(source, span.start, span.range.start),
+            end: JsonPosition::new(source, span.end, span.range.end),
+            bytes: span.range.clone(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonPosition {
+    /// Line, starting from 1
+    line: usize,
+    /// Column in characters, starting from 1
+    column: usize,
+}
+
+impl JsonPosition {
+    fn new(source: &str, position: Position, offset: usize) -> Self {
+        Self {
+            line: position.line,
+            column: char_column(source, position, offset),
+        }
+    }
+}
+
+/// A hunk of a unified diff
+#[derive(Serialize)]
+struct JsonHunk {
+    old_start: usize,
+    old_lines: usize,
+    new_start: usize,
+    new_lines: usize,
+    /// The lines prefixed with ' ', '-' or '+' like in unified diffs
+    lines: Vec<String>,
+}
+
+impl From<&Hunk<'_, str>> for JsonHunk {
+    fn from(hunk: &Hunk<'_, str>) -> Self {
+        Self {
+            old_start: hunk.old_range().start(),
+            old_lines: hunk.old_range().len(),
+            new_start: hunk.new_range().start(),
+            new_lines: hunk.new_range().len(),
+            lines: hunk
+                .lines()
+                .iter()
+                .map(|line| {
+                    let (prefix, text) = match line {
+                        Line::Context(text) => (' ', text),
+                        Line::Delete(text) => ('-', text),
+                        Line::Insert(text) => ('+', text),
+                    };
+                    format!("{prefix}{}", text.trim_end_matches('\n'))
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The column in characters, starting from 1, of the byte `offset` at the `position`
+fn char_column(source: &str, position: Position, offset: usize) -> usize {
+    source[offset + 1 - position.column..offset].chars().count() + 1
+}
+
+/// Renders an error like rustc does, with the source line and the error location underlined
+fn render_error(file: &Path, source: &str, error: &FormatError) -> String {
+    let mut rendered = format!("error: {}\n", error.message());
+    let Some(span) = error.span() else {
+        rendered.push_str(&format!(" --> {}\n", file.display()));
+        return rendered;
+    };
+    let line_start = span.range.start + 1 - span.start.column;
+    let line = source[line_start..].lines().next().unwrap_or("");
+    let underlined = source[span.range.clone()].lines().next().unwrap_or("");
+    // Columns are counted in characters
+    let column = char_column(source, span.start, span.range.start);
+    let gutter = " ".repeat(span.start.line.to_string().len());
+    rendered.push_str(&format!(
+        "{gutter}--> {}:{}:{column}\n",
+        file.display(),
+        span.start.line
+    ));
+    rendered.push_str(&format!("{gutter} |\n"));
+    rendered.push_str(&format!("{} | {line}\n", span.start.line));
+    rendered.push_str(&format!(
+        "{gutter} | {}{}\n",
+        " ".repeat(column - 1),
+        "^".repeat(underlined.chars().count().max(1))
+    ));
+    rendered
+}
+
+fn read_file(file: &Path, is_stdin: bool) -> Result<String> {
+    if is_stdin {
+        io::read_to_string(io::stdin()).context("Error while reading stdin")
+    } else {
+        fs::read_to_string(file).with_context(|| format!("Error while reading {}", file.display()))
+    }
+}
+
+/// Settings read from a `turtlefmt.toml` file or from the `[tool.turtlefmt]` section of a `pyproject.toml` file
+///
+/// The settings that are not set are taken from the configuration of the parent directories.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Config {
+    /// Do not look for configuration files in the parent directories
+    root: bool,
+    indentation: Option<usize>,
+    fix_prefixes: Option<bool>,
+    prefixes: Option<Vec<PathBuf>>,
+    validate_language_tags: Option<bool>,
+    canonical_literals: Option<bool>,
+    string_quoting: Option<QuotingArg>,
+    escaping: Option<EscapingArg>,
+    nfc: Option<NfcArg>,
+    verbatim_comments: Option<bool>,
+    max_width: Option<usize>,
+    join_comment_lines: Option<bool>,
+    align_comments: Option<bool>,
+    keep_invalid_statements: Option<bool>,
+    max_errors: Option<NonZeroUsize>,
+    extensions: Option<Vec<String>>,
+    include: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+    follow_symlinks: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct PyProject {
+    tool: Option<PyProjectTool>,
+}
+
+#[derive(Deserialize)]
+struct PyProjectTool {
+    turtlefmt: Option<Config>,
+}
+
+impl Config {
+    /// Reads the configuration file of `dir`, if any
+    fn load(dir: &Path) -> Result<Option<Self>> {
+        let file = dir.join("turtlefmt.toml");
+        let mut config = if file.is_file() {
+            toml::from_str(&fs::read_to_string(&file)?)
+                .with_context(|| format!("Error while reading {}", file.display()))?
+        } else {
+            let file = dir.join("pyproject.toml");
+            if !file.is_file() {
+                return Ok(None);
+            }
+            let pyproject: PyProject = toml::from_str(&fs::read_to_string(&file)?)
+                .with_context(|| format!("Error while reading {}", file.display()))?;
+            let Some(config) = pyproject.tool.and_then(|tool| tool.turtlefmt) else {
+                return Ok(None);
+            };
+            config
+        };
+        // The paths and patterns are relative to the configuration file
+        for file in config.prefixes.iter_mut().flatten() {
+            *file = dir.join(&*file);
+        }
+        let dir_pattern = globset::escape(&dir.to_string_lossy());
+        for patterns in [&mut config.include, &mut config.exclude]
+            .into_iter()
+            .flatten()
+        {
+            for pattern in patterns {
+                *pattern = format!("{dir_pattern}/{pattern}");
+            }
+        }
+        Ok(Some(config))
+    }
+
+    /// Takes the settings not set in `self` from `parent`
+    fn or(self, parent: Self) -> Self {
+        Self {
+            root: self.root,
+            indentation: self.indentation.or(parent.indentation),
+            fix_prefixes: self.fix_prefixes.or(parent.fix_prefixes),
+            prefixes: self.prefixes.or(parent.prefixes),
+            validate_language_tags: self
+                .validate_language_tags
+                .or(parent.validate_language_tags),
+            canonical_literals: self.canonical_literals.or(parent.canonical_literals),
+            string_quoting: self.string_quoting.or(parent.string_quoting),
+            escaping: self.escaping.or(parent.escaping),
+            nfc: self.nfc.or(parent.nfc),
+            verbatim_comments: self.verbatim_comments.or(parent.verbatim_comments),
+            max_width: self.max_width.or(parent.max_width),
+            join_comment_lines: self.join_comment_lines.or(parent.join_comment_lines),
+            align_comments: self.align_comments.or(parent.align_comments),
+            keep_invalid_statements: self
+                .keep_invalid_statements
+                .or(parent.keep_invalid_statements),
+            max_errors: self.max_errors.or(parent.max_errors),
+            extensions: self.extensions.or(parent.extensions),
+            include: self.include.or(parent.include),
+            exclude: self.exclude.or(parent.exclude),
+            follow_symlinks: self.follow_symlinks.or(parent.follow_symlinks),
+        }
+    }
+}
+
+/// The settings of the files of a directory, from its configuration and the command line
+struct Settings {
+    options: Arc<FormatOptions>,
+    extensions: Vec<String>,
+    /// Patterns of the configuration, with absolute paths
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    follow_symlinks: bool,
+}
+
+impl Settings {
+    /// If the file found in a directory has to be formatted
+    fn selects(&self, file: &Path) -> Result<bool> {
+        if !file.extension().is_some_and(|extension| {
+            self.extensions
+                .iter()
+                .any(|e| OsStr::new(e.trim_start_matches('.')) == extension)
+        }) {
+            return Ok(false);
+        }
+        let file = std::path::absolute(file)?;
+        Ok(self
+            .include
+            .as_ref()
+            .map_or(true, |include| include.is_match(&file))
+            && !file.ancestors().any(|path| self.exclude.is_match(path)))
+    }
+}
+
+/// Finds the settings of each directory, caching the configurations
+struct Project<'a> {
+    args: &'a Args,
+    configs: HashMap<PathBuf, Config>,
+    settings: HashMap<PathBuf, Arc<Settings>>,
+    /// The prefixes read from the prefix files by canonical path
+    prefixes: HashMap<PathBuf, HashMap<String, String>>,
+}
+
+impl<'a> Project<'a> {
+    fn new(args: &'a Args) -> Self {
+        Self {
+            args,
+            configs: HashMap::new(),
+            settings: HashMap::new(),
+            prefixes: HashMap::new(),
+        }
+    }
+
+    /// The settings to use for `file`
+    fn settings(&mut self, file: &Path) -> Result<Arc<Settings>> {
+        let file = std::path::absolute(file)?;
+        self.dir_settings(file.parent().unwrap_or(&file))
+    }
+
+    /// The prefixes declared in `file`, read only once even if shared by several configurations
+    fn prefixes(&mut self, file: &Path) -> Result<&HashMap<String, String>> {
+        let path = fs::canonicalize(file)
+            .with_context(|| format!("Error while reading {}", file.display()))?;
+        if !self.prefixes.contains_key(&path) {
+            let content = fs::read_to_string(file)
+                .with_context(|| format!("Error while reading {}", file.display()))?;
+            let prefixes = extract_prefixes(&content)
+                .with_context(|| format!("Error while reading prefixes from {}", file.display()))?;
+            self.prefixes.insert(path.clone(), prefixes);
+        }
+        Ok(&self.prefixes[&path])
+    }
+
+    fn dir_settings(&mut self, dir: &Path) -> Result<Arc<Settings>> {
+        if let Some(settings) = self.settings.get(dir) {
+            return Ok(Arc::clone(settings));
+        }
+        let config = self.config(dir)?;
+        let args = self.args;
+        let mut extra_prefixes = HashMap::new();
+        let prefixes = if args.prefixes.is_empty() {
+            config.prefixes.as_deref().unwrap_or_default()
         } else {
-            fs::write(&file, formatted)?;
+            &args.prefixes
+        };
+        for file in prefixes {
+            extra_prefixes.extend(
+                self.prefixes(file)?
+                    .iter()
+                    .map(|(prefix, iri)| (prefix.clone(), iri.clone())),
+            );
         }
+        let options = FormatOptions {
+            indentation: args.indentation.or(config.indentation).unwrap_or(4),
+            declare_missing_prefixes: args
+                .fix_prefixes
+                .or(args.no_fix_prefixes)
+                .or(config.fix_prefixes)
+                .unwrap_or(false),
+            extra_prefixes,
+            validate_language_tags: args
+                .validate_language_tags
+                .or(args.no_validate_language_tags)
+                .or(config.validate_language_tags)
+                .unwrap_or(false),
+            canonicalize_literals: args
+                .canonical_literals
+                .or(args.no_canonical_literals)
+                .or(config.canonical_literals)
+                .unwrap_or(false),
+            string_quoting: args
+                .string_quoting
+                .or(config.string_quoting)
+                .unwrap_or(QuotingArg::Double)
+                .into(),
+            escaping: args
+                .escaping
+                .or(config.escaping)
+                .unwrap_or(EscapingArg::Minimal)
+                .into(),
+            nfc: args.nfc.or(config.nfc).unwrap_or(NfcArg::Ignore).into(),
+            verbatim_comments: args
+                .verbatim_comments
+                .or(args.no_verbatim_comments)
+                .or(config.verbatim_comments)
+                .unwrap_or(false),
+            max_width: args.max_width.or(config.max_width),
+            join_comment_lines: args
+                .join_comment_lines
+                .or(args.no_join_comment_lines)
+                .or(config.join_comment_lines)
+                .unwrap_or(false),
+            align_comments: args
+                .align_comments
+                .or(args.no_align_comments)
+                .or(config.align_comments)
+                .unwrap_or(false),
+            keep_invalid_statements: args
+                .keep_invalid_statements
+                .or(args.no_keep_invalid_statements)
+                .or(config.keep_invalid_statements)
+                .unwrap_or(false),
+            max_errors: args
+                .max_errors
+                .or(config.max_errors)
+                .unwrap_or(NonZeroUsize::new(20).unwrap()),
+        };
+        let extensions = if args.extensions.is_empty() {
+            config.extensions.unwrap_or_else(|| vec!["ttl".into()])
+        } else {
+            args.extensions.clone()
+        };
+        // The patterns given on the command line replace the ones of the configuration
+        let include = match config.include {
+            Some(include) if args.include.is_empty() => Some(build_glob_set(&include)?),
+            _ => None,
+        };
+        let exclude = match config.exclude {
+            Some(exclude) if args.exclude.is_empty() => build_glob_set(&exclude)?,
+            _ => GlobSet::empty(),
+        };
+        let settings = Arc::new(Settings {
+            options: Arc::new(options),
+            extensions,
+            include,
+            exclude,
+            follow_symlinks: args
+                .follow_symlinks
+                .or(args.no_follow_symlinks)
+                .or(config.follow_symlinks)
+                .unwrap_or(false),
+        });
+        self.settings.insert(dir.to_owned(), Arc::clone(&settings));
+        Ok(settings)
     }
-    Ok(exit_code)
+
+    fn config(&mut self, dir: &Path) -> Result<Config> {
+        if let Some(config) = self.configs.get(dir) {
+            return Ok(config.clone());
+        }
+        let parent = |project: &mut Self| {
+            dir.parent()
+                .map_or_else(|| Ok(Config::default()), |parent| project.config(parent))
+        };
+        let config = match Config::load(dir)? {
+            Some(config) if config.root => config,
+            Some(config) => config.or(parent(self)?),
+            None => parent(self)?,
+        };
+        self.configs.insert(dir.to_owned(), config.clone());
+        Ok(config)
+    }
+}
+
+/// Which files to format in the directories, from the command line
+struct FileSelection {
+    /// If not empty, the files must match one of these patterns
+    include: GlobSet,
+    exclude: GlobSet,
 }
 
-fn add_files_with_suffix(dir: &Path, extension: &OsStr, files: &mut Vec<PathBuf>) -> Result<()> {
-    for entry in fs::read_dir(dir)? {
-        let entry = entry?;
-        let entry_type = entry.file_type()?;
-        if entry_type.is_file() {
-            let file = entry.path();
-            if file.extension() == Some(extension) {
-                files.push(file);
+impl FileSelection {
+    /// The files to format with their options from the targets of the command line
+    ///
+    /// With --keep-going, the missing targets are kept to be reported as failed files.
+    fn targets(
+        &self,
+        is_stdin: bool,
+        project: &mut Project<'_>,
+    ) -> Result<Vec<(PathBuf, Arc<FormatOptions>)>> {
+        let args = project.args;
+        let mut files = Vec::new();
+        if is_stdin {
+            // The configuration is looked for from the directory of --stdin-filepath
+            let file = args
+                .stdin_filepath
+                .clone()
+                .unwrap_or_else(|| PathBuf::from("<stdin>"));
+            let options = project.settings(&file)?.options.clone();
+            files.push((file, options));
+            return Ok(files);
+        }
+        for source in args.src.iter().cloned() {
+            if source.is_file() {
+                let options = project.settings(&source)?.options.clone();
+                files.push((source, options));
+            } else if source.is_dir() {
+                self.add_files(&source, project, &mut files)?;
+            } else if args.keep_going {
+                files.push((source, Arc::default()));
+            } else {
+                bail!(
+                    "The target to format {} does not seem to exist",
+                    source.display()
+                );
             }
-        } else if entry_type.is_dir() {
-            add_files_with_suffix(&entry.path(), extension, files)?;
         }
+        Ok(files)
+    }
+
+    /// Adds the files of `dir` and its subdirectories, skipping the ignored ones and the `.git` directories
+    fn add_files(
+        &self,
+        dir: &Path,
+        project: &mut Project<'_>,
+        files: &mut Vec<(PathBuf, Arc<FormatOptions>)>,
+    ) -> Result<()> {
+        let walker = WalkBuilder::new(dir)
+            .follow_links(
+                project
+                    .dir_settings(&std::path::absolute(dir)?)?
+                    .follow_symlinks,
+            )
+            .hidden(false)
+            .require_git(false)
+            .add_custom_ignore_filename(".turtlefmtignore")
+            .filter_entry({
+                let exclude = self.exclude.clone();
+                let dir = dir.to_owned();
+                move |entry| {
+                    entry.file_name() != ".git"
+                        && !exclude
+                            .is_match(entry.path().strip_prefix(&dir).unwrap_or(entry.path()))
+                }
+            })
+            .sort_by_file_path(Path::cmp)
+            .build();
+        for entry in walker {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(error) if is_loop(&error) => {
+                    // We do not go again in a directory linked from itself
+                    eprintln!("Warning: {error}");
+                    continue;
+                }
+                Err(error) => return Err(error.into()),
+            };
+            if !entry.file_type().is_some_and(|t| t.is_file()) {
+                continue;
+            }
+            let file = entry.into_path();
+            let relative = file.strip_prefix(dir).unwrap_or(&file);
+            if !self.include.is_empty() && !self.include.is_match(relative) {
+                continue;
+            }
+            let settings = project.settings(&file)?;
+            if settings.selects(&file)? {
+                files.push((file, Arc::clone(&settings.options)));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_loop(error: &ignore::Error) -> bool {
+    match error {
+        ignore::Error::Loop { .. } => true,
+        ignore::Error::WithPath { err, .. }
+        | ignore::Error::WithDepth { err, .. }
+        | ignore::Error::WithLineNumber { err, .. } => is_loop(err),
+        _ => false,
+    }
+}
+
+fn build_glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern).with_context(|| format!("Invalid glob pattern {pattern}"))?);
+    }
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates an empty directory isolated from the configuration files of its parents
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("turtlefmt-{}-{name}", std::process::id()));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("turtlefmt.toml"), "root = true\n").unwrap();
+        dir
+    }
+
+    fn write(file: PathBuf, content: &str) {
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(file, content).unwrap();
+    }
+
+    fn parse_args(args: &[&str]) -> Args {
+        Args::try_parse_from(["turtlefmt"].iter().chain(args)).unwrap()
+    }
+
+    fn first_error(source: &str) -> FormatError {
+        Formatter::new()
+            .format(source, &FormatOptions::default())
+            .unwrap_err()
+            .0
+            .remove(0)
+    }
+
+    #[test]
+    fn test_render_error_non_ascii() {
+        let source = "<s> <p> \"é€\" , ex:o .\n";
+        let error = first_error(source);
+        let span = error.span().unwrap();
+        assert_eq!(span.start.column, 19);
+        assert_eq!(char_column(source, span.start, span.range.start), 16);
+        assert_eq!(
+            render_error(Path::new("test.ttl"), source, &error),
+            concat!(
+                "error: The prefix ex: is not defined\n",
+                " --> test.ttl:1:16\n",
+                "  |\n",
+                "1 | <s> <p> \"é€\" , ex:o .\n",
+                "  |                ^^^\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_render_error_missing_at_end_of_line() {
+        let source = "<s> <p> \"é\"\n<s2> <p> <o> .\n";
+        let error = first_error(source);
+        assert_eq!(error.span().unwrap().start.column, 13);
+        assert_eq!(
+            render_error(Path::new("test.ttl"), source, &error),
+            concat!(
+                "error: Syntax error: expected '.' to end the statement\n",
+                " --> test.ttl:1:12\n",
+                "  |\n",
+                "1 | <s> <p> \"é\"\n",
+                "  |            ^\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_sarif_log() {
+        let source = "<s> <p> \"é€\" , ex:o .\n";
+        let mut report = FileReport::new(Path::new("dir/test.ttl"), FileStatus::WouldChange);
+        report.diagnostics = vec![JsonDiagnostic::from_error(source, &first_error(source))];
+        let patch = create_patch("", "<s> <p> <o> .\n");
+        report.hunks = Some(patch.hunks().iter().map(JsonHunk::from).collect());
+        let log = sarif_log(report.sarif_results());
+        assert_eq!(log["version"], "2.1.0");
+        let results = log["runs"][0]["results"].as_array().unwrap();
+        assert_eq!(results.len(), 2);
+
+        assert_eq!(results[0]["ruleId"], "undefined-prefix");
+        assert_eq!(results[0]["level"], "error");
+        let location = &results[0]["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "dir/test.ttl");
+        // The columns of "ex:" count the characters, not the bytes
+        assert_eq!(
+            location["region"],
+            json!({ "startLine": 1, "startColumn": 16, "endLine": 1, "endColumn": 19 })
+        );
+
+        // The hunk only inserts lines: its region is the line before the insertion
+        assert_eq!(results[1]["ruleId"], "format");
+        assert_eq!(
+            results[1]["locations"][0]["physicalLocation"]["region"],
+            json!({ "startLine": 1, "endLine": 1 })
+        );
+    }
+
+    #[test]
+    fn test_keep_going_missing_target() {
+        let dir = test_dir("keep-going");
+        write(dir.join("a.ttl"), "<s> <p> <o> .\n");
+        let missing = dir.join("missing.ttl").display().to_string();
+        let present = dir.join("a.ttl").display().to_string();
+        let selection = FileSelection {
+            include: GlobSet::empty(),
+            exclude: GlobSet::empty(),
+        };
+
+        let args = parse_args(&[&missing, &present]);
+        assert!(selection.targets(false, &mut Project::new(&args)).is_err());
+
+        let args = parse_args(&["--keep-going", &missing, &present]);
+        let files = selection.targets(false, &mut Project::new(&args)).unwrap();
+        let files = files.into_iter().map(|(file, _)| file).collect::<Vec<_>>();
+        assert_eq!(files, [dir.join("missing.ttl"), dir.join("a.ttl")]);
+        assert!(read_file(&files[0], false).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let mut summary = Summary::default();
+        summary.add(FileStatus::new("<s> <p> <o> .\n", "<s> <p> <o> .\n", true));
+        summary.add(FileStatus::new("<s> <p> <o>.\n", "<s> <p> <o> .\n", false));
+        assert_eq!(summary.exit_code(), 0);
+        assert_eq!(
+            summary.to_string(),
+            "2 files: 1 formatted, 1 already formatted"
+        );
+
+        let args = parse_args(&["--list-different", "a.ttl"]);
+        assert!(args.check());
+        summary.add(FileStatus::new(
+            "<s> <p> <o>.\n",
+            "<s> <p> <o> .\n",
+            args.check(),
+        ));
+        assert_eq!(summary.exit_code(), EXIT_NOT_FORMATTED);
+
+        // The failures take precedence over the files not formatted
+        summary.add(FileStatus::Error);
+        assert_eq!(summary.exit_code(), EXIT_ERROR);
+        assert_eq!(
+            summary.to_string(),
+            "4 files: 1 formatted, 1 not formatted, 1 already formatted, 1 failed"
+        );
+    }
+
+    fn selected_files(dir: &Path, args: &[&str]) -> Vec<PathBuf> {
+        let args = parse_args(args);
+        let selection = FileSelection {
+            include: build_glob_set(&args.include).unwrap(),
+            exclude: build_glob_set(&args.exclude).unwrap(),
+        };
+        let mut files = Vec::new();
+        selection
+            .add_files(dir, &mut Project::new(&args), &mut files)
+            .unwrap();
+        files
+            .into_iter()
+            .map(|(file, _)| file.strip_prefix(dir).unwrap().to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_add_files() {
+        let dir = test_dir("add-files");
+        for file in [
+            "a.ttl",
+            "b.nt",
+            "gitignored.ttl",
+            "ignored.ttl",
+            ".git/c.ttl",
+            ".hidden/d.ttl",
+            "sub/e.ttl",
+            "sub/f.ttl",
+            "vendor/g.ttl",
+        ] {
+            write(dir.join(file), "");
+        }
+        write(dir.join(".gitignore"), "gitignored.ttl\n");
+        write(dir.join(".turtlefmtignore"), "ignored.ttl\n");
+        assert_eq!(
+            selected_files(&dir, &["."]),
+            [
+                Path::new(".hidden/d.ttl"),
+                Path::new("a.ttl"),
+                Path::new("sub/e.ttl"),
+                Path::new("sub/f.ttl"),
+                Path::new("vendor/g.ttl"),
+            ]
+        );
+        // The patterns are matched against the paths relative to the directory
+        assert_eq!(
+            selected_files(&dir, &["--include", "sub/*", "--exclude", "sub/f.ttl", "."]),
+            [Path::new("sub/e.ttl")]
+        );
+        assert_eq!(
+            selected_files(&dir, &["--exclude", "vendor", "--exclude", ".*", "."]),
+            [
+                Path::new("a.ttl"),
+                Path::new("sub/e.ttl"),
+                Path::new("sub/f.ttl")
+            ]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_add_files_symlink_loop() {
+        let dir = test_dir("add-files-loop");
+        write(dir.join("sub/a.ttl"), "");
+        std::os::unix::fs::symlink(&dir, dir.join("sub/loop")).unwrap();
+        // The loop is only warned about and the files are listed once
+        assert_eq!(
+            selected_files(&dir, &["--follow-symlinks", "."]),
+            [Path::new("sub/a.ttl")]
+        );
+        assert_eq!(selected_files(&dir, &["."]), [Path::new("sub/a.ttl")]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_config_load() {
+        let dir = test_dir("config-load");
+        write(
+            dir.join("turtlefmt.toml"),
+            "indentation = 2\nprefixes = [\"p.ttl\"]\ninclude = [\"*.ttl\"]\n",
+        );
+        let config = Config::load(&dir).unwrap().unwrap();
+        assert_eq!(config.indentation, Some(2));
+        assert_eq!(config.prefixes, Some(vec![dir.join("p.ttl")]));
+        assert_eq!(
+            config.include,
+            Some(vec![format!(
+                "{}/*.ttl",
+                globset::escape(&dir.to_string_lossy())
+            )])
+        );
+
+        let sub = dir.join("sub");
+        write(sub.join("pyproject.toml"), "[project]\nname = \"test\"\n");
+        assert!(Config::load(&sub).unwrap().is_none());
+        write(
+            sub.join("pyproject.toml"),
+            "[tool.turtlefmt]\nstring-quoting = \"adaptive\"\nmax-errors = 3\n",
+        );
+        let config = Config::load(&sub).unwrap().unwrap();
+        assert!(matches!(config.string_quoting, Some(QuotingArg::Adaptive)));
+        assert_eq!(config.max_errors, NonZeroUsize::new(3));
+
+        write(dir.join("turtlefmt.toml"), "unknown = true\n");
+        assert!(Config::load(&dir).is_err());
+        write(dir.join("turtlefmt.toml"), "max-errors = 0\n");
+        assert!(Config::load(&dir).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_config_or() {
+        let child = Config {
+            indentation: Some(2),
+            fix_prefixes: Some(false),
+            ..Config::default()
+        };
+        let parent = Config {
+            root: true,
+            indentation: Some(8),
+            fix_prefixes: Some(true),
+            align_comments: Some(true),
+            ..Config::default()
+        };
+        let config = child.or(parent);
+        assert!(!config.root);
+        assert_eq!(config.indentation, Some(2));
+        assert_eq!(config.fix_prefixes, Some(false));
+        assert_eq!(config.align_comments, Some(true));
+        assert_eq!(config.max_width, None);
+    }
+
+    #[test]
+    fn test_nested_configs() {
+        let dir = test_dir("nested-configs");
+        write(
+            dir.join("turtlefmt.toml"),
+            "root = true\nindentation = 2\nalign-comments = true\n",
+        );
+        write(
+            dir.join("a/pyproject.toml"),
+            "[tool.turtlefmt]\nindentation = 8\n",
+        );
+        write(dir.join("b/turtlefmt.toml"), "root = true\n");
+        let args = parse_args(&[]);
+        let mut project = Project::new(&args);
+        let options = &project.settings(&dir.join("x.ttl")).unwrap().options;
+        assert_eq!(options.indentation, 2);
+        assert!(options.align_comments);
+        let options = &project.settings(&dir.join("a/c/x.ttl")).unwrap().options;
+        assert_eq!(options.indentation, 8);
+        assert!(options.align_comments);
+        let options = &project.settings(&dir.join("b/x.ttl")).unwrap().options;
+        assert_eq!(options.indentation, 4);
+        assert!(!options.align_comments);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_shared_prefix_file() {
+        let dir = test_dir("shared-prefixes");
+        write(
+            dir.join("turtlefmt.toml"),
+            "root = true\nprefixes = [\"p.ttl\"]\n",
+        );
+        write(dir.join("p.ttl"), "@prefix ex: <http://example.com/> .\n");
+        write(
+            dir.join("a/turtlefmt.toml"),
+            "indentation = 2\nprefixes = [\"../p.ttl\"]\n",
+        );
+        let args = parse_args(&[]);
+        let mut project = Project::new(&args);
+        for file in ["x.ttl", "a/x.ttl", "b/x.ttl"] {
+            let options = &project.settings(&dir.join(file)).unwrap().options;
+            assert_eq!(options.extra_prefixes["ex"], "http://example.com/");
+        }
+        // The file is read once whatever the path used to reference it
+        assert_eq!(project.prefixes.len(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_command_line_overrides_config() {
+        let dir = test_dir("overrides");
+        write(
+            dir.join("turtlefmt.toml"),
+            "root = true\nindentation = 2\nalign-comments = true\n",
+        );
+        let file = dir.join("x.ttl");
+        let args = parse_args(&["--indentation", "3", "--no-align-comments"]);
+        let options = &Project::new(&args).settings(&file).unwrap().options;
+        assert_eq!(options.indentation, 3);
+        assert!(!options.align_comments);
+
+        let args = parse_args(&["--no-align-comments", "--align-comments"]);
+        let options = &Project::new(&args).settings(&file).unwrap().options;
+        assert!(options.align_comments);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_settings_selects() {
+        let dir = test_dir("selects");
+        write(
+            dir.join("turtlefmt.toml"),
+            "root = true\nextensions = [\"ttl\", \"owl\"]\ninclude = [\"src/**\"]\nexclude = [\"src/gen\"]\n",
+        );
+        let args = parse_args(&[]);
+        let mut project = Project::new(&args);
+        let settings = project.settings(&dir.join("x.ttl")).unwrap();
+        assert!(settings.selects(&dir.join("src/a.ttl")).unwrap());
+        assert!(settings.selects(&dir.join("src/sub/b.owl")).unwrap());
+        assert!(!settings.selects(&dir.join("src/c.nt")).unwrap());
+        assert!(!settings.selects(&dir.join("src/gen/d.ttl")).unwrap());
+        assert!(!settings.selects(&dir.join("e.ttl")).unwrap());
+
+        // The patterns given on the command line replace the ones of the configuration
+        let args = parse_args(&["--include", "*.ttl"]);
+        let settings = Project::new(&args).settings(&dir.join("x.ttl")).unwrap();
+        assert!(settings.selects(&dir.join("e.ttl")).unwrap());
+        assert!(!settings.selects(&dir.join("src/gen/d.ttl")).unwrap());
+        fs::remove_dir_all(dir).unwrap();
     }
-    Ok(())
 }
@@ -14,7 +14,17 @@
     limitations under the License.
 */
 
-use turtlefmt::{format_turtle, FormatOptions};
+use std::num::NonZeroUsize;
+use turtlefmt::{
+    extract_prefixes, format_turtle, format_turtle_range, format_turtle_with_warnings,
+    CharacterEscaping, FormatError, FormatOptions, Formatter, NfcPolicy, Position, StringQuoting,
+};
+
+/// Checks that `input` is formatted into `expected` and that `expected` is kept as is
+fn assert_formatted(input: &str, expected: &str, options: &FormatOptions) {
+    assert_eq!(format_turtle(input, options).unwrap(), expected);
+    assert_eq!(format_turtle(expected, options).unwrap(), expected);
+}
 
 #[test]
 fn test_format() {
@@ -34,3 +44,430 @@ fn test_stable() {
         file
     );
 }
+
+#[test]
+fn test_declare_missing_prefixes() {
+    let input =
+        "@prefix ex: <http://example.com/> .\n<s> a owl:Class ; ex:p \"foo\"^^xsd:string .\n";
+    let expected = "@prefix ex: <http://example.com/> .\n@prefix owl: <http://www.w3.org/2002/07/owl#> .\n@prefix xsd: <http://www.w3.org/2001/XMLSchema#> .\n\n<s> a owl:Class ;\n    ex:p \"foo\"^^xsd:string .\n";
+    let options = FormatOptions {
+        declare_missing_prefixes: true,
+        ..FormatOptions::default()
+    };
+    assert_eq!(format_turtle(input, &options).unwrap(), expected);
+    assert_eq!(format_turtle(expected, &options).unwrap(), expected);
+    assert!(format_turtle(input, &FormatOptions::default()).is_err());
+}
+
+#[test]
+fn test_extra_prefixes() {
+    let header = "@prefix ex: <http://example.com/> .\nPREFIX schema: <http://schema.org/>\n";
+    let options = FormatOptions {
+        extra_prefixes: extract_prefixes(header).unwrap(),
+        ..FormatOptions::default()
+    };
+    let fragment = "ex:s   schema:name 'foo' .\n";
+    assert_eq!(
+        format_turtle(fragment, &options).unwrap(),
+        "ex:s schema:name \"foo\" .\n"
+    );
+    assert!(format_turtle(fragment, &FormatOptions::default()).is_err());
+}
+
+#[test]
+fn test_language_tags() {
+    let input =
+        "<s> <p> \"a\"@EN-us , \"b\"@zh-hant-tw , \"c\"@de-ch-1996 , \"d\"@en-a-BBB-x-CC .\n";
+    let expected =
+        "<s> <p> \"a\"@en-US , \"b\"@zh-Hant-TW , \"c\"@de-CH-1996 , \"d\"@en-a-bbb-x-cc .\n";
+    let options = FormatOptions {
+        validate_language_tags: true,
+        ..FormatOptions::default()
+    };
+    assert_eq!(format_turtle(input, &options).unwrap(), expected);
+
+    let error = format_turtle("<s> <p> \"a\"@en-US-a .\n", &options)
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("line 1 between bytes 19 and 20"), "{error}");
+    assert!(format_turtle("<s> <p> \"a\"@abcdefghi .\n", &options).is_err());
+    assert!(format_turtle("<s> <p> \"a\"@abcdefghi .\n", &FormatOptions::default()).is_ok());
+}
+
+#[test]
+fn test_canonicalize_literals() {
+    let options = FormatOptions {
+        canonicalize_literals: true,
+        ..FormatOptions::default()
+    };
+    assert_formatted(
+        include_str!("from.canonical.ttl"),
+        include_str!("to.canonical.ttl"),
+        &options,
+    );
+}
+
+#[test]
+fn test_adaptive_string_quoting() {
+    let options = FormatOptions {
+        string_quoting: StringQuoting::Adaptive,
+        ..FormatOptions::default()
+    };
+    assert_formatted(
+        include_str!("from.quoting.ttl"),
+        include_str!("to.quoting.ttl"),
+        &options,
+    );
+}
+
+#[test]
+fn test_invisible_character_escaping() {
+    let options = FormatOptions {
+        escaping: CharacterEscaping::Invisible,
+        ..FormatOptions::default()
+    };
+    assert_formatted(
+        include_str!("from.invisible.ttl"),
+        include_str!("to.invisible.ttl"),
+        &options,
+    );
+}
+
+#[test]
+fn test_ascii_escaping() {
+    let options = FormatOptions {
+        escaping: CharacterEscaping::Ascii,
+        ..FormatOptions::default()
+    };
+    let expected = include_str!("to.ascii.ttl");
+    assert_formatted(include_str!("from.ascii.ttl"), expected, &options);
+    assert!(expected.is_ascii());
+}
+
+#[test]
+fn test_nfc() {
+    // The file uses "e" followed by a combining acute accent instead of "é"
+    let input = include_str!("from.nfc.ttl");
+    let options = FormatOptions {
+        nfc: NfcPolicy::Report,
+        ..FormatOptions::default()
+    };
+    let (formatted, warnings) = format_turtle_with_warnings(input, &options).unwrap();
+    assert_eq!(formatted, include_str!("to.nfc.ttl"));
+    assert_eq!(
+        warnings.iter().map(|w| w.line).collect::<Vec<_>>(),
+        [2, 2, 3]
+    );
+    assert_eq!(
+        warnings
+            .iter()
+            .map(|w| &input[w.span.range.clone()])
+            .collect::<Vec<_>>(),
+        ["http://example.com/e\u{301}", "\"e\\u0301\"", "ex:e\u{301}"]
+    );
+
+    let options = FormatOptions {
+        nfc: NfcPolicy::NormalizeLiterals,
+        ..FormatOptions::default()
+    };
+    let (formatted, warnings) = format_turtle_with_warnings(input, &options).unwrap();
+    assert_eq!(formatted, include_str!("to.nfc-normalized.ttl"));
+    assert_eq!(warnings.len(), 2);
+
+    let (_, warnings) = format_turtle_with_warnings(input, &FormatOptions::default()).unwrap();
+    assert!(warnings.is_empty());
+}
+
+#[test]
+fn test_format_comments() {
+    let input = include_str!("from.comments.ttl");
+    let expected = include_str!("to.comments.ttl");
+    assert_eq!(
+        format_turtle(input, &FormatOptions::default()).unwrap(),
+        expected
+    );
+    assert_eq!(
+        format_turtle(expected, &FormatOptions::default()).unwrap(),
+        expected
+    );
+}
+
+#[test]
+fn test_verbatim_comments() {
+    let input = "##########\n#  License  #\n##########\n\n#!  keep   me\n<s> <p> <o> . #no space\n";
+    assert_eq!(
+        format_turtle(input, &FormatOptions::default()).unwrap(),
+        "# #########\n# License  #\n# #########\n\n#!  keep   me\n<s> <p> <o> . # no space\n"
+    );
+    let options = FormatOptions {
+        verbatim_comments: true,
+        ..FormatOptions::default()
+    };
+    assert_eq!(
+        format_turtle(input, &options).unwrap(),
+        "##########\n#  License  #\n##########\n\n#!  keep   me\n<s> <p> <o> . #no space\n"
+    );
+}
+
+#[test]
+fn test_wrap_comments() {
+    let input = include_str!("from.wrap.ttl");
+    let options = FormatOptions {
+        max_width: Some(30),
+        ..FormatOptions::default()
+    };
+    assert_formatted(input, include_str!("to.wrap.ttl"), &options);
+
+    let options = FormatOptions {
+        max_width: Some(30),
+        join_comment_lines: true,
+        ..FormatOptions::default()
+    };
+    assert_formatted(input, include_str!("to.wrap-joined.ttl"), &options);
+}
+
+#[test]
+fn test_align_comments() {
+    let options = FormatOptions {
+        align_comments: true,
+        ..FormatOptions::default()
+    };
+    assert_formatted(
+        include_str!("from.align.ttl"),
+        include_str!("to.align.ttl"),
+        &options,
+    );
+}
+
+#[test]
+fn test_format_off_regions() {
+    assert_formatted(
+        include_str!("from.off.ttl"),
+        include_str!("to.off.ttl"),
+        &FormatOptions::default(),
+    );
+
+    // The directives are neither wrapped nor joined with the other comments
+    let input = "# Note\n# turtlefmt: skip\n<s>    <p> <o> .\n";
+    for max_width in [12, 80] {
+        let options = FormatOptions {
+            max_width: Some(max_width),
+            join_comment_lines: true,
+            ..FormatOptions::default()
+        };
+        assert_formatted(input, input, &options);
+    }
+}
+
+#[test]
+fn test_format_range() {
+    let input = "@prefix ex: <http://example.com/> .\nex:a   ex:b ex:c .\nex:d   ex:e ex:f ;   ex:g ex:h . # end\n\n\nex:i   ex:j ex:k .\n";
+    let options = FormatOptions::default();
+    let start = input.find("ex:d").unwrap();
+    assert_eq!(
+        format_turtle_range(input, start..start + 4, &options).unwrap(),
+        "@prefix ex: <http://example.com/> .\nex:a   ex:b ex:c .\nex:d ex:e ex:f ;\n    ex:g ex:h . # end\n\n\nex:i   ex:j ex:k .\n"
+    );
+    assert_eq!(
+        format_turtle_range(input, 0..start, &options).unwrap(),
+        "@prefix ex: <http://example.com/> .\nex:a ex:b ex:c .\nex:d   ex:e ex:f ;   ex:g ex:h . # end\n\n\nex:i   ex:j ex:k .\n"
+    );
+    assert_eq!(
+        format_turtle_range(input, input.len()..input.len(), &options).unwrap(),
+        input
+    );
+    assert!(format_turtle_range(input, 0..input.len() + 1, &options).is_err());
+
+    // Invalid statements outside of the range are ignored
+    let input = "@prefix ex: <http://example.com/> .\nex:a   ex:b ex:c .\nex:d ex:e \"f .\n";
+    assert_eq!(
+        format_turtle_range(input, 36..37, &options).unwrap(),
+        "@prefix ex: <http://example.com/> .\nex:a ex:b ex:c .\nex:d ex:e \"f .\n"
+    );
+    assert!(format_turtle_range(input, input.len() - 3..input.len() - 2, &options).is_err());
+    let options = FormatOptions {
+        keep_invalid_statements: true,
+        ..FormatOptions::default()
+    };
+    assert_eq!(
+        format_turtle_range(input, input.len() - 3..input.len() - 2, &options).unwrap(),
+        input
+    );
+}
+
+#[test]
+fn test_format_full_range() {
+    let options = FormatOptions::default();
+    for input in [
+        "<a> <b> <c> . # turtlefmt: off\n\n<s>  <p> <o> .\n",
+        "# turtlefmt: skip\n\n# note\n<s>  <p> <o> .\n",
+        "# turtlefmt: skip\n# note\n<s>  <p> <o> .\n\n<s>  <p> <o2> .\n",
+        include_str!("to.off.ttl"),
+    ] {
+        assert_eq!(
+            format_turtle_range(input, 0..input.len(), &options).unwrap(),
+            format_turtle(input, &options).unwrap()
+        );
+    }
+}
+
+#[test]
+fn test_formatter_reuse() {
+    let mut formatter = Formatter::new();
+    let options = FormatOptions::default();
+    assert_eq!(
+        formatter.format("<s>   <p> <o> .", &options).unwrap(),
+        "<s> <p> <o> .\n"
+    );
+    assert!(formatter.format("<s> <p> .", &options).is_err());
+    assert_eq!(
+        formatter.format("<s> <p>   ( 1 2 ) .", &options).unwrap(),
+        "<s> <p> ( 1 2 ) .\n"
+    );
+}
+
+#[test]
+fn test_keep_invalid_statements() {
+    let input = include_str!("from.invalid.ttl");
+    assert!(format_turtle(input, &FormatOptions::default()).is_err());
+
+    let options = FormatOptions {
+        keep_invalid_statements: true,
+        ..FormatOptions::default()
+    };
+    let (formatted, warnings) = format_turtle_with_warnings(input, &options).unwrap();
+    assert_eq!(formatted, include_str!("to.invalid.ttl"));
+    assert_eq!(warnings.iter().map(|w| w.line).collect::<Vec<_>>(), [3, 4]);
+    assert_eq!(format_turtle(&formatted, &options).unwrap(), formatted);
+}
+
+#[test]
+fn test_errors() {
+    let options = FormatOptions::default();
+    let FormatError::Syntax { span, missing } =
+        format_turtle("<s> <p> <o>\n<s2> <p> <o> .\n", &options)
+            .unwrap_err()
+            .0
+            .remove(0)
+    else {
+        panic!("expecting a syntax error")
+    };
+    assert_eq!(missing.as_deref(), Some("."));
+    assert_eq!(
+        span.start,
+        Position {
+            line: 1,
+            column: 12
+        }
+    );
+
+    // The parser guesses an arbitrary missing object that is not worth reporting
+    let error = format_turtle("<s> <p> .\n", &options)
+        .unwrap_err()
+        .0
+        .remove(0);
+    assert!(matches!(error, FormatError::Syntax { missing: None, .. }));
+    assert_eq!(error.message(), "Syntax error");
+
+    let FormatError::UndefinedPrefix { span, prefix } =
+        format_turtle("<s> <p>\n    ex:o .\n", &options)
+            .unwrap_err()
+            .0
+            .remove(0)
+    else {
+        panic!("expecting an undefined prefix error")
+    };
+    assert_eq!(prefix, "ex");
+    assert_eq!(span.range, 12..15);
+    assert_eq!(span.start, Position { line: 2, column: 5 });
+    assert_eq!(span.end, Position { line: 2, column: 8 });
+
+    let FormatError::InvalidEscape { span, .. } =
+        format_turtle("<s> <p> \"\"\"a\nb\\uD800\"\"\" .\n", &options)
+            .unwrap_err()
+            .0
+            .remove(0)
+    else {
+        panic!("expecting an invalid escape error")
+    };
+    assert_eq!(span.range, 14..20);
+    assert_eq!(span.start, Position { line: 2, column: 2 });
+
+    let FormatError::InvalidIriCharacter { span, character } =
+        format_turtle("<s> <p> <a\\u0020b> .\n", &options)
+            .unwrap_err()
+            .0
+            .remove(0)
+    else {
+        panic!("expecting an invalid IRI character error")
+    };
+    assert_eq!(character, ' ');
+    assert_eq!(span.range, 10..16);
+}
+
+#[test]
+fn test_all_errors() {
+    let input = "<s> <p> <o>\n<s> <p> ex:o .\n<s> <p> \"\\uD800\" .\n<s> <p> ( <o> .\n";
+    let errors = format_turtle(input, &FormatOptions::default())
+        .unwrap_err()
+        .0;
+    assert_eq!(
+        errors
+            .iter()
+            .map(|e| e.span().unwrap().start.line)
+            .collect::<Vec<_>>(),
+        [1, 2, 3, 4]
+    );
+    assert_eq!(
+        errors[0].message(),
+        "Syntax error: expected '.' to end the statement"
+    );
+    assert_eq!(
+        errors[0].to_string(),
+        "Syntax error: expected '.' to end the statement on line 1 between bytes 12 and 12"
+    );
+
+    let options = FormatOptions {
+        max_errors: NonZeroUsize::new(2).unwrap(),
+        ..FormatOptions::default()
+    };
+    assert_eq!(format_turtle(input, &options).unwrap_err().0.len(), 2);
+    // All the errors of a statement are reported
+    let input = "@prefix ex: <http://example.com/> .\n@prefix bad: <http://example.com/\\u0020> .\n<s> bar:p foo:o ; ex:q \"\\uD800\" , <a\\u0020b> .\n<s> baz:p <o> .\n";
+    let errors = format_turtle(input, &FormatOptions::default())
+        .unwrap_err()
+        .0;
+    assert_eq!(
+        errors.iter().map(FormatError::message).collect::<Vec<_>>(),
+        [
+            "The character ' ' is not allowed in IRIs",
+            "The prefix bar: is not defined",
+            "The prefix foo: is not defined",
+            "The escaped unicode character '\\uD800' is not encoding a valid unicode character",
+            "The character ' ' is not allowed in IRIs",
+            "The prefix baz: is not defined",
+        ]
+    );
+}
+
+#[test]
+fn test_max_errors_one() {
+    let options = FormatOptions {
+        max_errors: NonZeroUsize::new(1).unwrap(),
+        ..FormatOptions::default()
+    };
+    assert_eq!(
+        format_turtle("<s>   <p> <o> .\n<s> <p> <o2> .", &options).unwrap(),
+        "<s> <p> <o> .\n\n<s> <p> <o2> .\n"
+    );
+    let input = "<s> <p> <o> .\n<s> <p> ex:o .\n<s> <p> ex:o2 .\n";
+    assert_eq!(format_turtle(input, &options).unwrap_err().0.len(), 1);
+    let options = FormatOptions {
+        keep_invalid_statements: true,
+        ..options
+    };
+    assert_eq!(
+        format_turtle(input, &options).unwrap(),
+        "<s> <p> <o> .\n\n<s> <p> ex:o .\n\n<s> <p> ex:o2 .\n"
+    );
+}